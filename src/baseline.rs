@@ -0,0 +1,572 @@
+// SPDX-License-Identifier: GPL-3.0-only
+//
+// Copyright (C) 2019-2023 Tony Asleson <tony.asleson@gmail.com>
+
+//! Baseline files, the duplicates which have been looked at and accepted.  The structured format
+//! is JSON and records enough context to know what an entry referred to, who accepted it and
+//! why.  The original format, one hash value per line with '#' comments, is still accepted.
+
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs;
+use std::str::FromStr;
+use std::time::SystemTime;
+
+use crate::report::{utc_timestamp, Duplicate, Instance};
+
+pub const BASELINE_VERSION: u32 = 1;
+
+/// One accepted duplicate.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BaselineEntry {
+    pub hash: u64,
+    /// Number of duplicated lines when the entry was captured.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub num_lines: Option<u32>,
+    /// Where the duplicate was when the entry was captured.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub instances: Vec<Instance>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub author: Option<String>,
+    /// Date, "YYYY-MM-DD", after which the entry no longer suppresses the duplicate.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires: Option<String>,
+    /// Line signatures of the duplicated text, used to recognize it after it has moved.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub content: Vec<u64>,
+}
+
+impl BaselineEntry {
+    /// An entry capturing a duplicate as it is now.
+    pub fn from_duplicate(
+        d: &Duplicate,
+        reason: Option<String>,
+        author: Option<String>,
+    ) -> BaselineEntry {
+        BaselineEntry {
+            hash: d.key,
+            num_lines: Some(d.num_lines),
+            instances: d
+                .instances
+                .iter()
+                .map(|i| Instance {
+                    snippet: None,
+                    ..i.clone()
+                })
+                .collect(),
+            reason,
+            author,
+            expires: None,
+            content: d.signatures.clone(),
+        }
+    }
+
+    pub fn from_hash(hash: u64) -> BaselineEntry {
+        BaselineEntry {
+            hash,
+            num_lines: None,
+            instances: vec![],
+            reason: None,
+            author: None,
+            expires: None,
+            content: vec![],
+        }
+    }
+
+    /// Has this entry expired as of `today`, "YYYY-MM-DD".  An entry expires at the end of the
+    /// day it lists.
+    pub fn expired(&self, today: &str) -> bool {
+        match &self.expires {
+            Some(e) => valid_date(e) && e.as_str() < today,
+            None => false,
+        }
+    }
+}
+
+/// How a duplicate relates to the baseline.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BaselineStatus {
+    /// Not in the baseline.
+    New,
+    /// Matches a baseline entry, but more lines are duplicated than when it was captured.
+    Grown,
+    /// In the baseline, possibly moved.
+    Unchanged,
+}
+
+impl fmt::Display for BaselineStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            BaselineStatus::New => "new",
+            BaselineStatus::Grown => "grown",
+            BaselineStatus::Unchanged => "unchanged",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+/// How duplicates are matched against baseline entries.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MatchMode {
+    /// The hash signature must be the same.
+    Exact,
+    /// Also match entries whose recorded locations or text overlap a duplicate enough.
+    Fuzzy,
+}
+
+impl FromStr for MatchMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "exact" => Ok(MatchMode::Exact),
+            "fuzzy" => Ok(MatchMode::Fuzzy),
+            _ => Err(format!(
+                "unknown value '{}', expected one of exact, fuzzy",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for MatchMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            MatchMode::Exact => "exact",
+            MatchMode::Fuzzy => "fuzzy",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+/// What `Baseline::update` did.
+#[derive(Debug, Default)]
+pub struct Changes {
+    pub added: usize,
+    pub removed: usize,
+    /// Entries replaced by one for the duplicate they matched, which has changed.
+    pub updated: usize,
+    pub unchanged: usize,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Baseline {
+    pub baseline_version: u32,
+    #[serde(default)]
+    pub entries: Vec<BaselineEntry>,
+}
+
+/// Is `d` a "YYYY-MM-DD" date, which also allows comparing them as strings.
+fn valid_date(d: &str) -> bool {
+    let b = d.as_bytes();
+    b.len() == 10
+        && b[4] == b'-'
+        && b[7] == b'-'
+        && b.iter()
+            .enumerate()
+            .all(|(i, c)| i == 4 || i == 7 || c.is_ascii_digit())
+}
+
+/// Today's date in UTC as "YYYY-MM-DD".
+pub fn today() -> String {
+    utc_timestamp(SystemTime::now())[..10].to_string()
+}
+
+impl Baseline {
+    pub fn new() -> Baseline {
+        Baseline {
+            baseline_version: BASELINE_VERSION,
+            entries: vec![],
+        }
+    }
+
+    /// Load a baseline file in either format.
+    pub fn load(file_name: &str) -> Result<Baseline, String> {
+        let content = fs::read_to_string(file_name)
+            .map_err(|e| format!("Unable to open baseline file {}, reason: {}", file_name, e))?;
+
+        if content.trim_start().starts_with('{') {
+            let b: Baseline = serde_json::from_str(&content)
+                .map_err(|e| format!("Invalid baseline file {}, reason: {}", file_name, e))?;
+            if b.baseline_version > BASELINE_VERSION {
+                return Err(format!(
+                    "Baseline file {} is version {}, we only understand up to {}",
+                    file_name, b.baseline_version, BASELINE_VERSION
+                ));
+            }
+            for e in &b.entries {
+                if let Some(d) = &e.expires {
+                    if !valid_date(d) {
+                        eprintln!(
+                            "WARNING: Baseline entry {} has invalid expiry \"{}\", \
+                            expected YYYY-MM-DD, ignoring expiry",
+                            e.hash, d
+                        );
+                    }
+                }
+            }
+            Ok(b)
+        } else {
+            Ok(Baseline::parse_plain(&content))
+        }
+    }
+
+    /// The original format, one hash value per line, blank lines and '#' comments are skipped.
+    fn parse_plain(content: &str) -> Baseline {
+        let mut b = Baseline::new();
+
+        for line in content.lines() {
+            let l = line.trim();
+
+            if !l.is_empty() && !l.starts_with('#') {
+                if let Ok(hv) = l.parse::<u64>() {
+                    b.entries.push(BaselineEntry::from_hash(hv));
+                } else {
+                    eprintln!("WARNING: Ignore file contains invalid hash value \"{}\"", l);
+                }
+            }
+        }
+        b
+    }
+
+    /// Bring the baseline in line with the duplicates found.  Duplicates which don't have an
+    /// entry are added, entries which don't match any duplicate are removed and the rest are
+    /// left as is.  An entry matched by a duplicate with a different hash, eg. one which grew,
+    /// is replaced by one for the duplicate as it is now, keeping the reason, author and expiry.
+    pub fn update(
+        &mut self,
+        duplicates: &[Duplicate],
+        mode: MatchMode,
+        threshold: f64,
+        reason: Option<String>,
+        author: Option<String>,
+    ) -> Changes {
+        let matcher = Matcher::new(
+            Baseline {
+                baseline_version: self.baseline_version,
+                entries: self.entries.clone(),
+            },
+            "",
+            mode,
+            threshold,
+        );
+        let mut changes = Changes::default();
+        // Entries matched by a duplicate with the same hash, and those matched by one which
+        // changed.
+        let mut kept: HashSet<u64> = HashSet::new();
+        let mut replaced: HashSet<u64> = HashSet::new();
+        let mut entries: Vec<BaselineEntry> = vec![];
+        let mut refreshed: Vec<BaselineEntry> = vec![];
+
+        for d in duplicates {
+            match matcher.classify(d) {
+                Some((_, hash)) if hash == d.key => {
+                    kept.insert(hash);
+                }
+                Some((_, hash)) => {
+                    if let Some(e) = self.entries.iter().find(|e| e.hash == hash) {
+                        let mut n =
+                            BaselineEntry::from_duplicate(d, e.reason.clone(), e.author.clone());
+                        n.expires = e.expires.clone();
+                        refreshed.push(n);
+                        replaced.insert(hash);
+                        changes.updated += 1;
+                    }
+                }
+                None => {
+                    entries.push(BaselineEntry::from_duplicate(
+                        d,
+                        reason.clone(),
+                        author.clone(),
+                    ));
+                    changes.added += 1;
+                }
+            }
+        }
+
+        changes.removed = self
+            .entries
+            .iter()
+            .filter(|e| !kept.contains(&e.hash) && !replaced.contains(&e.hash))
+            .count();
+        self.entries.retain(|e| kept.contains(&e.hash));
+        changes.unchanged = self.entries.len();
+        self.entries.extend(refreshed);
+        self.entries.extend(entries);
+
+        // Always written in the current structured format.
+        self.baseline_version = BASELINE_VERSION;
+        changes
+    }
+
+    pub fn save(&self, file_name: &str) -> Result<(), String> {
+        let mut content = serde_json::to_string_pretty(self)
+            .map_err(|e| format!("Unable to serialize baseline, reason: {}", e))?;
+        content.push('\n');
+        fs::write(file_name, content)
+            .map_err(|e| format!("Unable to write baseline file {}, reason: {}", file_name, e))
+    }
+}
+
+/// Decides which duplicates the baseline accepts.  Expired entries are reported and left out
+/// when it's created.
+pub struct Matcher {
+    entries: Vec<BaselineEntry>,
+    by_hash: HashMap<u64, usize>,
+    mode: MatchMode,
+    /// Fraction, 0.0 - 1.0, of an entry that a duplicate has to overlap for a fuzzy match.
+    threshold: f64,
+    by_path: HashMap<String, Vec<usize>>,
+    by_content: HashMap<u64, Vec<usize>>,
+}
+
+impl Matcher {
+    /// A matcher which doesn't accept anything.
+    pub fn empty() -> Matcher {
+        Matcher::new(Baseline::new(), "", MatchMode::Exact, 1.0)
+    }
+
+    pub fn new(b: Baseline, today: &str, mode: MatchMode, threshold: f64) -> Matcher {
+        let mut m = Matcher {
+            entries: vec![],
+            by_hash: HashMap::new(),
+            mode,
+            threshold,
+            by_path: HashMap::new(),
+            by_content: HashMap::new(),
+        };
+
+        for e in b.entries {
+            if e.expired(today) {
+                eprintln!(
+                    "NOTE: Baseline entry {} expired on {}, no longer ignored",
+                    e.hash,
+                    e.expires.as_deref().unwrap_or_default()
+                );
+                continue;
+            }
+
+            let index = m.entries.len();
+            m.by_hash.insert(e.hash, index);
+            if mode == MatchMode::Fuzzy {
+                for i in &e.instances {
+                    m.by_path.entry(i.path.clone()).or_default().push(index);
+                }
+                for sig in e.content.iter().collect::<HashSet<_>>() {
+                    m.by_content.entry(*sig).or_default().push(index);
+                }
+            }
+            m.entries.push(e);
+        }
+        m
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Hash values of all the active entries.
+    pub fn hashes(&self) -> impl Iterator<Item = u64> + '_ {
+        self.entries.iter().map(|e| e.hash)
+    }
+
+    /// Find the baseline entry for a duplicate, returning how it relates to it and the entry's
+    /// hash, None means it's new.
+    pub fn classify(&self, d: &Duplicate) -> Option<(BaselineStatus, u64)> {
+        if let Some(&i) = self.by_hash.get(&d.key) {
+            return Some((BaselineStatus::Unchanged, self.entries[i].hash));
+        }
+        if self.mode == MatchMode::Exact {
+            return None;
+        }
+
+        let mut candidates: HashSet<usize> = HashSet::new();
+        for i in &d.instances {
+            if let Some(c) = self.by_path.get(&i.path) {
+                candidates.extend(c);
+            }
+        }
+        for sig in &d.signatures {
+            if let Some(c) = self.by_content.get(sig) {
+                candidates.extend(c);
+            }
+        }
+
+        let current: HashSet<u64> = d.signatures.iter().copied().collect();
+        let best = candidates
+            .into_iter()
+            .map(|c| {
+                let e = &self.entries[c];
+                (location_overlap(e, d).max(content_overlap(e, &current)), c)
+            })
+            .filter(|(score, _)| *score >= self.threshold)
+            .max_by(|a, b| a.0.total_cmp(&b.0).then_with(|| b.1.cmp(&a.1)))?;
+
+        let e = &self.entries[best.1];
+        let status = match e.num_lines {
+            Some(n) if d.num_lines > n => BaselineStatus::Grown,
+            _ => BaselineStatus::Unchanged,
+        };
+        Some((status, e.hash))
+    }
+}
+
+/// Fraction of the lines recorded for an entry which are covered by the duplicate's instances.
+fn location_overlap(e: &BaselineEntry, d: &Duplicate) -> f64 {
+    let total: u64 = e
+        .instances
+        .iter()
+        .map(|i| (i.end_line + 1).saturating_sub(i.start_line) as u64)
+        .sum();
+    if total == 0 {
+        return 0.0;
+    }
+
+    let covered: u64 = e
+        .instances
+        .iter()
+        .map(|r| {
+            d.instances
+                .iter()
+                .filter(|i| i.path == r.path)
+                .map(|i| {
+                    (r.end_line.min(i.end_line) + 1).saturating_sub(r.start_line.max(i.start_line))
+                        as u64
+                })
+                .max()
+                .unwrap_or(0)
+        })
+        .sum();
+    covered as f64 / total as f64
+}
+
+/// Fraction of the entry's recorded lines of text found in the duplicate.
+fn content_overlap(e: &BaselineEntry, current: &HashSet<u64>) -> f64 {
+    if e.content.is_empty() {
+        return 0.0;
+    }
+    let found = e.content.iter().filter(|s| current.contains(s)).count();
+    found as f64 / e.content.len() as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::report::test_duplicate;
+
+    fn dup(key: u64, num_lines: u32, at: &[(&str, u32)], signatures: &[u64]) -> Duplicate {
+        Duplicate {
+            signatures: signatures.to_vec(),
+            ..test_duplicate(key, num_lines, at)
+        }
+    }
+
+    fn reviewed(d: &Duplicate) -> BaselineEntry {
+        let mut e = BaselineEntry::from_duplicate(d, Some("generated".into()), Some("tony".into()));
+        e.expires = Some("2030-01-01".into());
+        e
+    }
+
+    #[test]
+    fn update_adds_and_removes() {
+        let kept = dup(1, 6, &[("a.c", 1), ("b.c", 1)], &[1, 2, 3, 4, 5, 6]);
+        let gone = dup(2, 6, &[("c.c", 1), ("d.c", 1)], &[7, 8, 9, 10, 11, 12]);
+        let fresh = dup(3, 6, &[("e.c", 1), ("f.c", 1)], &[13, 14, 15, 16, 17, 18]);
+        let mut b = Baseline::new();
+        b.entries = vec![reviewed(&kept), reviewed(&gone)];
+
+        let c = b.update(
+            &[kept, fresh],
+            MatchMode::Exact,
+            0.5,
+            Some("new".into()),
+            None,
+        );
+        assert_eq!((c.added, c.removed, c.updated, c.unchanged), (1, 1, 0, 1));
+        let hashes: Vec<u64> = b.entries.iter().map(|e| e.hash).collect();
+        assert_eq!(hashes, vec![1, 3]);
+        assert_eq!(b.entries[0].reason.as_deref(), Some("generated"));
+        assert_eq!(b.entries[1].reason.as_deref(), Some("new"));
+    }
+
+    #[test]
+    fn update_keeps_metadata_of_grown_entry() {
+        let before = dup(1, 6, &[("a.c", 10), ("b.c", 20)], &[1, 2, 3, 4, 5, 6]);
+        let grown = dup(9, 8, &[("a.c", 10), ("b.c", 20)], &[1, 2, 3, 4, 5, 6, 7, 8]);
+        let mut b = Baseline::new();
+        b.entries = vec![reviewed(&before)];
+
+        let c = b.update(&[grown], MatchMode::Fuzzy, 0.5, Some("new".into()), None);
+        assert_eq!((c.added, c.removed, c.updated, c.unchanged), (0, 0, 1, 0));
+        assert_eq!(b.entries.len(), 1);
+        let e = &b.entries[0];
+        assert_eq!((e.hash, e.num_lines), (9, Some(8)));
+        assert_eq!(e.reason.as_deref(), Some("generated"));
+        assert_eq!(e.author.as_deref(), Some("tony"));
+        assert_eq!(e.expires.as_deref(), Some("2030-01-01"));
+    }
+
+    fn matcher(entries: Vec<BaselineEntry>, mode: MatchMode) -> Matcher {
+        let mut b = Baseline::new();
+        b.entries = entries;
+        Matcher::new(b, "2025-01-01", mode, 0.5)
+    }
+
+    #[test]
+    fn classify_exact() {
+        let d = dup(1, 6, &[("a.c", 1), ("b.c", 1)], &[1, 2, 3, 4, 5, 6]);
+        let m = matcher(vec![reviewed(&d)], MatchMode::Exact);
+        assert_eq!(m.classify(&d), Some((BaselineStatus::Unchanged, 1)));
+
+        let moved = dup(2, 6, &[("a.c", 1), ("b.c", 1)], &[1, 2, 3, 4, 5, 6]);
+        assert_eq!(m.classify(&moved), None);
+    }
+
+    #[test]
+    fn classify_fuzzy_by_location() {
+        let d = dup(1, 6, &[("a.c", 10), ("b.c", 10)], &[1, 2, 3, 4, 5, 6]);
+        let m = matcher(vec![reviewed(&d)], MatchMode::Fuzzy);
+
+        let grown = dup(
+            2,
+            8,
+            &[("a.c", 10), ("b.c", 10)],
+            &[21, 22, 23, 24, 25, 26, 27, 28],
+        );
+        assert_eq!(m.classify(&grown), Some((BaselineStatus::Grown, 1)));
+
+        let shrunk = dup(3, 4, &[("a.c", 11), ("b.c", 11)], &[2, 3, 4, 5]);
+        assert_eq!(m.classify(&shrunk), Some((BaselineStatus::Unchanged, 1)));
+
+        // Only a third of the entry is covered.
+        let other = dup(4, 6, &[("a.c", 14), ("c.c", 1)], &[31, 32, 33, 34, 35, 36]);
+        assert_eq!(m.classify(&other), None);
+    }
+
+    #[test]
+    fn classify_fuzzy_by_content() {
+        let d = dup(1, 6, &[("a.c", 10), ("b.c", 10)], &[1, 2, 3, 4, 5, 6]);
+        let m = matcher(vec![reviewed(&d)], MatchMode::Fuzzy);
+
+        let moved = dup(2, 6, &[("x.c", 1), ("y.c", 50)], &[1, 2, 3, 4, 5, 7]);
+        assert_eq!(m.classify(&moved), Some((BaselineStatus::Unchanged, 1)));
+
+        let rewritten = dup(3, 6, &[("x.c", 1), ("y.c", 50)], &[1, 2, 7, 8, 9, 10]);
+        assert_eq!(m.classify(&rewritten), None);
+    }
+
+    #[test]
+    fn expired_entries_are_dropped() {
+        let d = dup(1, 6, &[("a.c", 1), ("b.c", 1)], &[1, 2, 3, 4, 5, 6]);
+        let mut e = reviewed(&d);
+        e.expires = Some("2024-12-31".into());
+        assert!(e.expired("2025-01-01"));
+        assert!(!e.expired("2024-12-31"));
+        assert!(matcher(vec![e], MatchMode::Exact).is_empty());
+    }
+}
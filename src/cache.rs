@@ -0,0 +1,222 @@
+// SPDX-License-Identifier: GPL-3.0-only
+//
+// Copyright (C) 2019-2023 Tony Asleson <tony.asleson@gmail.com>
+
+//! Signature cache, `--cache <file>`, so that files which haven't changed since the last run
+//! don't have to be read and hashed again.  A file is unchanged when its size and modification
+//! time are the same, or failing that when the hash of its content is.
+
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::BufReader;
+use std::sync::Arc;
+use std::time::UNIX_EPOCH;
+
+use crate::{calculate_hash, hash_algorithm, rolling_hashes, save_bincode, signatures, Normalize};
+
+/// Bump this whenever the layout of the cache file changes.
+const CACHE_VERSION: u32 = 1;
+
+/// The settings the cached values depend on, a cache made with different ones is discarded.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct Stamp {
+    cache_version: u32,
+    hash_algorithm: String,
+    normalize: String,
+    /// Window size of the rolling hashes.
+    lines: u32,
+}
+
+impl Stamp {
+    fn new(lines: u32, normalize: Normalize) -> Stamp {
+        Stamp {
+            cache_version: CACHE_VERSION,
+            hash_algorithm: hash_algorithm(),
+            normalize: normalize.to_string(),
+            lines,
+        }
+    }
+}
+
+/// What we know about a file from the last time it was scanned.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Entry {
+    size: u64,
+    mtime_secs: u64,
+    mtime_nanos: u32,
+    content_hash: u64,
+    signatures: Vec<u64>,
+    suppressed: Vec<(u32, u32)>,
+    rolling: Vec<(u64, u32)>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct CacheFile {
+    stamp: Stamp,
+    entries: HashMap<String, Entry>,
+}
+
+/// The signatures, suppressed line ranges and rolling hashes of a file.
+pub type FileData = (Vec<u64>, Vec<(u32, u32)>, Vec<(u64, u32)>);
+
+pub struct Cache {
+    stamp: Stamp,
+    loaded: HashMap<String, Entry>,
+    /// Entries for the files which were read this run.
+    updated: DashMap<String, Entry>,
+}
+
+/// Size and modification time, (seconds, nanoseconds) since the epoch, of a file.
+fn file_stat(filename: &str) -> Option<(u64, u64, u32)> {
+    let m = fs::metadata(filename).ok()?;
+    let t = m.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+    Some((m.len(), t.as_secs(), t.subsec_nanos()))
+}
+
+impl Cache {
+    /// Load the cache, a missing, unreadable or out of date cache is simply empty.
+    pub fn load(file_name: &str, lines: u32, normalize: Normalize) -> Cache {
+        let stamp = Stamp::new(lines, normalize);
+        let loaded = File::open(file_name)
+            .ok()
+            .and_then(|f| bincode::deserialize_from::<_, CacheFile>(BufReader::new(f)).ok())
+            .filter(|c| c.stamp == stamp)
+            .map(|c| c.entries)
+            .unwrap_or_default();
+
+        Cache {
+            stamp,
+            loaded,
+            updated: DashMap::new(),
+        }
+    }
+
+    /// The data for a file, from the cache when the file is unchanged, else by reading it.
+    pub fn file(&self, filename: &str) -> FileData {
+        let stat = file_stat(filename);
+        let cached = self.loaded.get(filename);
+
+        if let (Some((size, secs, nanos)), Some(e)) = (stat, cached) {
+            if e.size == size && e.mtime_secs == secs && e.mtime_nanos == nanos {
+                return (
+                    e.signatures.clone(),
+                    e.suppressed.clone(),
+                    e.rolling.clone(),
+                );
+            }
+        }
+
+        let content = match fs::read(filename) {
+            Ok(c) => c,
+            Err(e) => {
+                eprintln!("ERROR: Unable to open {}, reason {}", filename, e);
+                return (Vec::new(), Vec::new(), Vec::new());
+            }
+        };
+        let content_hash = calculate_hash(&content);
+        let (size, mtime_secs, mtime_nanos) = stat.unwrap_or_default();
+
+        let entry = match cached {
+            // Touched, but not changed, eg. by a git checkout.
+            Some(e) if e.content_hash == content_hash => Entry {
+                size,
+                mtime_secs,
+                mtime_nanos,
+                ..e.clone()
+            },
+            _ => {
+                let (signatures, suppressed) = signatures(&content[..], filename);
+                let rolling = rolling_hashes(&signatures, self.stamp.lines as usize);
+                Entry {
+                    size,
+                    mtime_secs,
+                    mtime_nanos,
+                    content_hash,
+                    signatures,
+                    suppressed,
+                    rolling,
+                }
+            }
+        };
+
+        let rc = (
+            entry.signatures.clone(),
+            entry.suppressed.clone(),
+            entry.rolling.clone(),
+        );
+        self.updated.insert(filename.to_string(), entry);
+        rc
+    }
+
+    /// Write the cache with the entries for the files scanned this run, so files which are no
+    /// longer scanned drop out of it.
+    pub fn save(mut self, file_name: &str, files: &[(u32, Arc<String>)]) -> Result<(), String> {
+        let mut entries = HashMap::new();
+        for (_, name) in files {
+            let name = name.as_str();
+            if let Some(e) = self
+                .updated
+                .remove(name)
+                .map(|(_, e)| e)
+                .or_else(|| self.loaded.remove(name))
+            {
+                entries.insert(name.to_string(), e);
+            }
+        }
+
+        let c = CacheFile {
+            stamp: self.stamp,
+            entries,
+        };
+
+        save_bincode(&c, file_name, "cache")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    fn write(file_name: &Path, stamp: Stamp) {
+        let mut entries = HashMap::new();
+        entries.insert(
+            "a.c".to_string(),
+            Entry {
+                size: 1,
+                mtime_secs: 2,
+                mtime_nanos: 3,
+                content_hash: 4,
+                signatures: vec![5],
+                suppressed: vec![],
+                rolling: vec![],
+            },
+        );
+        let f = File::create(file_name).unwrap();
+        bincode::serialize_into(f, &CacheFile { stamp, entries }).unwrap();
+    }
+
+    #[test]
+    fn stale_cache_is_discarded() {
+        let file_name =
+            std::env::temp_dir().join(format!("duplihere-cache-{}", std::process::id()));
+        let name = file_name.to_string_lossy().to_string();
+
+        write(&file_name, Stamp::new(6, Normalize::Trim));
+        assert_eq!(Cache::load(&name, 6, Normalize::Trim).loaded.len(), 1);
+        assert!(Cache::load(&name, 7, Normalize::Trim).loaded.is_empty());
+        assert!(Cache::load(&name, 6, Normalize::Whitespace)
+            .loaded
+            .is_empty());
+
+        // Made by a build whose hasher gives different values.
+        let mut other = Stamp::new(6, Normalize::Trim);
+        other.hash_algorithm = format!("DefaultHasher/{}/0", env!("CARGO_PKG_VERSION"));
+        write(&file_name, other);
+        assert!(Cache::load(&name, 6, Normalize::Trim).loaded.is_empty());
+
+        fs::remove_file(&file_name).unwrap();
+    }
+}
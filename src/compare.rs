@@ -0,0 +1,401 @@
+// SPDX-License-Identifier: GPL-3.0-only
+//
+// Copyright (C) 2019-2023 Tony Asleson <tony.asleson@gmail.com>
+
+//! Differences between two saved JSON reports, `compare old.json new.json`.  Duplicates are
+//! matched by their hash signature first, then by where their instances are, so one which grew
+//! or shrank is reported as changed rather than as removed and new.  Paths are compared as they
+//! are shown in the reports.
+
+use serde::Serialize;
+use std::cmp::Reverse;
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+use std::io::{self, Write};
+use std::str::FromStr;
+
+use crate::report::{Duplicate, Report};
+
+/// The formats a comparison can be written in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    Text,
+    Json,
+    Markdown,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(Format::Text),
+            "json" => Ok(Format::Json),
+            "markdown" => Ok(Format::Markdown),
+            _ => Err(format!(
+                "unknown format '{}', expected one of text, json, markdown",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Format::Text => "text",
+            Format::Json => "json",
+            Format::Markdown => "markdown",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+/// A duplicate in both reports whose instances or length differ.
+#[derive(Debug, Serialize)]
+pub struct Changed {
+    pub old: Duplicate,
+    pub new: Duplicate,
+    /// Change in the duplicated lines, see `Duplicate::duplicate_lines`.
+    pub delta: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Comparison {
+    pub old_generated: String,
+    pub new_generated: String,
+    pub old_duplicate_lines: u64,
+    pub new_duplicate_lines: u64,
+    /// Net change in the duplicated lines.
+    pub delta: i64,
+    /// Duplicates which are the same in both reports.
+    pub unchanged: u64,
+    pub new: Vec<Duplicate>,
+    pub removed: Vec<Duplicate>,
+    pub changed: Vec<Changed>,
+}
+
+/// The places a duplicate is, used to tell if it changed.
+fn locations(d: &Duplicate) -> BTreeSet<(&str, u32, u32)> {
+    d.instances
+        .iter()
+        .map(|i| (i.path.as_str(), i.start_line, i.end_line))
+        .collect()
+}
+
+/// Find the duplicates of `old` for the ones in `new`, returning the index of the match in
+/// `old` for each of `new`.
+fn match_duplicates(old: &[Duplicate], new: &[Duplicate]) -> Vec<Option<usize>> {
+    let mut rc: Vec<Option<usize>> = vec![None; new.len()];
+    let mut taken = vec![false; old.len()];
+
+    let mut by_key: HashMap<u64, Vec<usize>> = HashMap::new();
+    for (n, d) in old.iter().enumerate() {
+        by_key.entry(d.key).or_default().push(n);
+    }
+    for (n, d) in new.iter().enumerate() {
+        if let Some(o) = by_key
+            .get(&d.key)
+            .and_then(|c| c.iter().find(|&&o| !taken[o]))
+        {
+            taken[*o] = true;
+            rc[n] = Some(*o);
+        }
+    }
+
+    // What is left is matched to the duplicate with the most instances overlapping its own.
+    let mut by_path: HashMap<&str, Vec<usize>> = HashMap::new();
+    for (n, d) in old.iter().enumerate().filter(|(n, _)| !taken[*n]) {
+        for i in &d.instances {
+            by_path.entry(i.path.as_str()).or_default().push(n);
+        }
+    }
+    for (n, d) in new.iter().enumerate() {
+        if rc[n].is_some() {
+            continue;
+        }
+        let mut overlaps: HashMap<usize, usize> = HashMap::new();
+        for i in &d.instances {
+            for &o in by_path.get(i.path.as_str()).into_iter().flatten() {
+                if !taken[o]
+                    && old[o].instances.iter().any(|oi| {
+                        oi.path == i.path
+                            && oi.start_line <= i.end_line
+                            && i.start_line <= oi.end_line
+                    })
+                {
+                    *overlaps.entry(o).or_default() += 1;
+                }
+            }
+        }
+        if let Some((o, _)) = overlaps
+            .into_iter()
+            .max_by_key(|&(o, count)| (count, Reverse(o)))
+        {
+            taken[o] = true;
+            rc[n] = Some(o);
+        }
+    }
+    rc
+}
+
+impl Comparison {
+    pub fn new(old: &Report, new: &Report) -> Comparison {
+        let matches = match_duplicates(&old.duplicates, &new.duplicates);
+        let mut matched = vec![false; old.duplicates.len()];
+        let mut rc = Comparison {
+            old_generated: old.generated.clone(),
+            new_generated: new.generated.clone(),
+            old_duplicate_lines: old.duplicates.iter().map(|d| d.duplicate_lines()).sum(),
+            new_duplicate_lines: new.duplicates.iter().map(|d| d.duplicate_lines()).sum(),
+            delta: 0,
+            unchanged: 0,
+            new: vec![],
+            removed: vec![],
+            changed: vec![],
+        };
+        rc.delta = rc.new_duplicate_lines as i64 - rc.old_duplicate_lines as i64;
+
+        for (d, m) in new.duplicates.iter().zip(matches) {
+            match m {
+                Some(o) => {
+                    matched[o] = true;
+                    let od = &old.duplicates[o];
+                    if od.num_lines == d.num_lines && locations(od) == locations(d) {
+                        rc.unchanged += 1;
+                    } else {
+                        rc.changed.push(Changed {
+                            old: od.clone(),
+                            new: d.clone(),
+                            delta: d.duplicate_lines() as i64 - od.duplicate_lines() as i64,
+                        });
+                    }
+                }
+                None => rc.new.push(d.clone()),
+            }
+        }
+        rc.removed = old
+            .duplicates
+            .iter()
+            .zip(matched)
+            .filter(|(_, m)| !m)
+            .map(|(d, _)| d.clone())
+            .collect();
+
+        rc.new.sort_by_key(|d| Reverse(d.duplicate_lines()));
+        rc.removed.sort_by_key(|d| Reverse(d.duplicate_lines()));
+        rc.changed.sort_by_key(|c| Reverse(c.delta));
+        rc
+    }
+
+    pub fn write(&self, format: Format, out: &mut dyn Write) -> io::Result<()> {
+        match format {
+            Format::Text => self.write_text(out),
+            Format::Json => {
+                serde_json::to_writer_pretty(&mut *out, self)?;
+                writeln!(out)
+            }
+            Format::Markdown => self.write_markdown(out),
+        }
+    }
+
+    fn write_text(&self, out: &mut dyn Write) -> io::Result<()> {
+        writeln!(
+            out,
+            "Comparing the report from {} with the one from {}\n\
+            {} new, {} removed, {} changed and {} unchanged duplicates.\n\
+            Duplicate lines {} -> {} ({:+})",
+            self.old_generated,
+            self.new_generated,
+            self.new.len(),
+            self.removed.len(),
+            self.changed.len(),
+            self.unchanged,
+            self.old_duplicate_lines,
+            self.new_duplicate_lines,
+            self.delta
+        )?;
+
+        for (title, dups, sign) in [("New", &self.new, 1), ("Removed", &self.removed, -1)] {
+            if dups.is_empty() {
+                continue;
+            }
+            writeln!(out, "\n{}:", title)?;
+            for d in dups {
+                writeln!(
+                    out,
+                    "{:+7}  {} lines x {}, hash {}\n         {}",
+                    sign * d.duplicate_lines() as i64,
+                    d.num_lines,
+                    d.instance_count,
+                    d.key,
+                    instance_list(d, |s| s)
+                )?;
+            }
+        }
+
+        if !self.changed.is_empty() {
+            writeln!(out, "\nChanged:")?;
+            for c in &self.changed {
+                writeln!(
+                    out,
+                    "{:+7}  {} -> {} lines x {} -> {}, hash {}\n         {}",
+                    c.delta,
+                    c.old.num_lines,
+                    c.new.num_lines,
+                    c.old.instance_count,
+                    c.new.instance_count,
+                    c.new.key,
+                    instance_list(&c.new, |s| s)
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    fn write_markdown(&self, out: &mut dyn Write) -> io::Result<()> {
+        writeln!(
+            out,
+            "### Duplication {} -> {} lines ({:+})\n\n\
+            | New | Removed | Changed | Unchanged |\n\
+            |---:|---:|---:|---:|\n\
+            | {} | {} | {} | {} |",
+            self.old_duplicate_lines,
+            self.new_duplicate_lines,
+            self.delta,
+            self.new.len(),
+            self.removed.len(),
+            self.changed.len(),
+            self.unchanged
+        )?;
+
+        let code = |s: String| format!("`{}`", s.replace('`', "'"));
+        for (title, dups, sign) in [
+            ("New duplicates", &self.new, 1),
+            ("Removed duplicates", &self.removed, -1),
+        ] {
+            if dups.is_empty() {
+                continue;
+            }
+            writeln!(
+                out,
+                "\n#### {}\n\n| Lines | Instances | Change | Locations |\n|---:|---:|---:|---|",
+                title
+            )?;
+            for d in dups {
+                writeln!(
+                    out,
+                    "| {} | {} | {:+} | {} |",
+                    d.num_lines,
+                    d.instance_count,
+                    sign * d.duplicate_lines() as i64,
+                    instance_list(d, code)
+                )?;
+            }
+        }
+
+        if !self.changed.is_empty() {
+            writeln!(
+                out,
+                "\n#### Changed duplicates\n\n| Lines | Instances | Change | Locations |\n\
+                |---:|---:|---:|---|"
+            )?;
+            for c in &self.changed {
+                writeln!(
+                    out,
+                    "| {} -> {} | {} -> {} | {:+} | {} |",
+                    c.old.num_lines,
+                    c.new.num_lines,
+                    c.old.instance_count,
+                    c.new.instance_count,
+                    c.delta,
+                    instance_list(&c.new, code)
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The first few locations of a duplicate, each formatted with `f`.
+fn instance_list(d: &Duplicate, f: impl Fn(String) -> String) -> String {
+    const SHOWN: usize = 3;
+    let mut rc: Vec<String> = d
+        .instances
+        .iter()
+        .take(SHOWN)
+        .map(|i| f(format!("{}:{}-{}", i.path, i.start_line, i.end_line)))
+        .collect();
+    if d.instances.len() > SHOWN {
+        rc.push(format!("(+{} more)", d.instances.len() - SHOWN));
+    }
+    rc.join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::report::{test_duplicate as dup, test_report as report};
+
+    #[test]
+    fn matched_by_key_then_location() {
+        let old = vec![
+            dup(1, 5, &[("a.c", 1), ("b.c", 1)]),
+            dup(2, 5, &[("a.c", 20), ("b.c", 20)]),
+            dup(3, 5, &[("c.c", 1), ("d.c", 1)]),
+            dup(4, 5, &[("e.c", 1), ("f.c", 1)]),
+        ];
+        let new = vec![
+            // Same text somewhere else is still the same duplicate.
+            dup(3, 5, &[("c.c", 40), ("d.c", 40)]),
+            // Grew, so the key changed, but it overlaps 2 by both instances and 1 by one.
+            dup(9, 8, &[("a.c", 18), ("b.c", 18), ("a.c", 3)]),
+            dup(1, 5, &[("a.c", 1), ("b.c", 1)]),
+            dup(10, 5, &[("g.c", 1), ("h.c", 1)]),
+        ];
+        assert_eq!(
+            match_duplicates(&old, &new),
+            vec![Some(2), Some(1), Some(0), None]
+        );
+    }
+
+    #[test]
+    fn location_matches_are_taken_once() {
+        let old = vec![dup(1, 5, &[("a.c", 1), ("b.c", 1)])];
+        let new = vec![
+            dup(8, 6, &[("a.c", 1), ("b.c", 1)]),
+            dup(9, 6, &[("a.c", 2), ("b.c", 2)]),
+        ];
+        assert_eq!(match_duplicates(&old, &new), vec![Some(0), None]);
+    }
+
+    #[test]
+    fn comparison() {
+        let old = report(vec![
+            dup(1, 5, &[("a.c", 1), ("b.c", 1)]),
+            dup(2, 5, &[("a.c", 20), ("b.c", 20)]),
+            dup(3, 4, &[("c.c", 1), ("d.c", 1)]),
+        ]);
+        let new = report(vec![
+            dup(1, 5, &[("a.c", 1), ("b.c", 1)]),
+            dup(9, 7, &[("a.c", 20), ("b.c", 20), ("e.c", 1)]),
+            dup(10, 3, &[("g.c", 1), ("h.c", 1)]),
+        ]);
+        let c = Comparison::new(&old, &new);
+        assert_eq!((c.old_duplicate_lines, c.new_duplicate_lines), (14, 22));
+        assert_eq!(c.delta, 8);
+        assert_eq!(c.unchanged, 1);
+        assert_eq!(c.new.iter().map(|d| d.key).collect::<Vec<_>>(), [10]);
+        assert_eq!(c.removed.iter().map(|d| d.key).collect::<Vec<_>>(), [3]);
+        assert_eq!(c.changed.len(), 1);
+        assert_eq!((c.changed[0].old.key, c.changed[0].new.key), (2, 9));
+        assert_eq!(c.changed[0].delta, 9);
+
+        let mut out = Vec::new();
+        c.write(Format::Markdown, &mut out).unwrap();
+        let md = String::from_utf8(out).unwrap();
+        assert!(md.starts_with("### Duplication 14 -> 22 lines (+8)"));
+        assert!(md.contains("| 1 | 1 | 1 | 1 |"));
+        assert!(md.contains("| 5 -> 7 | 2 -> 3 | +9 | `a.c:20-26`, `b.c:20-26`, `e.c:1-7` |"));
+    }
+}
@@ -0,0 +1,333 @@
+// SPDX-License-Identifier: GPL-3.0-only
+//
+// Copyright (C) 2019-2023 Tony Asleson <tony.asleson@gmail.com>
+
+//! Project configuration file, `duplihere.toml`, found by walking up from the current working
+//! directory.  Relative paths and globs in it are relative to the directory it's in.  Command
+//! line options take precedence over anything in it.
+
+use glob::Pattern;
+use serde::Deserialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::{Limit, Options};
+
+pub const CONFIG_FILE: &str = "duplihere.toml";
+
+/// Settings which can be changed for the files matching a `[paths."<glob>"]` section.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PathOverride {
+    /// Minimum number of duplicate lines for instances in these files.
+    pub lines: Option<u32>,
+    /// Don't scan these files at all.
+    pub exclude: Option<bool>,
+}
+
+/// A path section with its glob made absolute.
+#[derive(Debug, Clone)]
+pub struct PathRule {
+    pub pattern: Pattern,
+    pub settings: PathOverride,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ConfigFile {
+    #[serde(default)]
+    files: Vec<String>,
+    #[serde(default)]
+    exclude: Vec<String>,
+    lines: Option<u32>,
+    print: Option<bool>,
+    #[serde(default)]
+    output: Vec<String>,
+    baseline: Option<String>,
+    baseline_match: Option<String>,
+    baseline_threshold: Option<f64>,
+    normalize: Option<String>,
+    root: Option<String>,
+    threads: Option<usize>,
+    cache: Option<String>,
+    max_duplicate_lines: Option<u64>,
+    max_duplication_percent: Option<f64>,
+    max_clones: Option<u64>,
+    fail_on_new: Option<bool>,
+    fail_on_stale_ignores: Option<bool>,
+    #[serde(default)]
+    paths: toml::Table,
+}
+
+/// Look for the configuration file in `start` and each of its parents.
+pub fn discover(start: &Path) -> Option<PathBuf> {
+    start
+        .ancestors()
+        .map(|d| d.join(CONFIG_FILE))
+        .find(|f| f.is_file())
+}
+
+/// The configuration file the user wants, from `--config <file>` or `--no-config` on the
+/// command line, else the one found by `discover`.  We need this before the command line is
+/// parsed as the configuration supplies the defaults.
+pub fn requested(args: &[String]) -> Option<PathBuf> {
+    let mut rc = None;
+    let mut i = 1;
+    while i < args.len() {
+        let a = &args[i];
+        if a == "--" {
+            break;
+        } else if a == "--no-config" {
+            return None;
+        } else if a == "--config" {
+            rc = args.get(i + 1).map(PathBuf::from);
+            i += 1;
+        } else if let Some(f) = a.strip_prefix("--config=") {
+            rc = Some(PathBuf::from(f));
+        }
+        i += 1;
+    }
+
+    rc.or_else(|| std::env::current_dir().ok().and_then(|d| discover(&d)))
+}
+
+/// Make a path from the configuration file absolute.
+fn resolve(dir: &Path, p: &str) -> String {
+    if Path::new(p).is_absolute() {
+        p.to_string()
+    } else {
+        dir.join(p).to_string_lossy().to_string()
+    }
+}
+
+/// Make a glob relative to `dir` absolute, escaping anything in `dir` which a glob would treat
+/// as a wildcard.
+fn absolute_glob(dir: &Path, glob: &str) -> String {
+    if Path::new(glob).is_absolute() {
+        glob.to_string()
+    } else {
+        format!(
+            "{}/{}",
+            Pattern::escape(&dir.to_string_lossy()),
+            glob.trim_start_matches("./")
+        )
+    }
+}
+
+/// Make a glob absolute, used for matching against canonical file names.
+pub fn absolute_pattern(dir: &Path, glob: &str) -> Result<Pattern, String> {
+    Pattern::new(&absolute_glob(dir, glob))
+        .map_err(|e| format!("Bad glob pattern '{}', error: {}", glob, e))
+}
+
+/// The values from the configuration file which can't simply be defaults for the command line
+/// options, as lists on the command line add to, rather than replace, what is there.
+#[derive(Debug, Default)]
+pub struct Lists {
+    pub files: Vec<String>,
+    pub outputs: Vec<String>,
+    /// Directory the configuration file is in.
+    pub dir: Option<PathBuf>,
+}
+
+/// Load the configuration file and apply it to the options as defaults.
+pub fn apply(file: &Path, opts: &mut Options) -> Result<Lists, String> {
+    let content = fs::read_to_string(file)
+        .map_err(|e| format!("Unable to read {}, reason: {}", file.display(), e))?;
+    let c: ConfigFile = toml::from_str(&content).map_err(|e| {
+        format!(
+            "Invalid configuration file {}, reason: {}",
+            file.display(),
+            e
+        )
+    })?;
+
+    let dir = fs::canonicalize(file)
+        .ok()
+        .and_then(|f| f.parent().map(Path::to_path_buf))
+        .unwrap_or_else(|| PathBuf::from("."));
+
+    if let Some(v) = c.lines {
+        opts.lines = v;
+    }
+    if let Some(v) = c.print {
+        opts.print = v;
+    }
+    if let Some(v) = c.baseline {
+        opts.ignore = resolve(&dir, &v);
+    }
+    if let Some(v) = c.baseline_match {
+        opts.baseline_match = v
+            .parse()
+            .map_err(|e| format!("{}: baseline_match {}", file.display(), e))?;
+    }
+    if let Some(v) = c.baseline_threshold {
+        opts.baseline_threshold = v;
+    }
+    if let Some(v) = c.normalize {
+        opts.normalize = v
+            .parse()
+            .map_err(|e| format!("{}: normalize {}", file.display(), e))?;
+    }
+    if let Some(v) = c.root {
+        opts.root = resolve(&dir, &v);
+    }
+    if let Some(v) = c.threads {
+        opts.threads = v;
+    }
+    if let Some(v) = c.cache {
+        opts.cache = resolve(&dir, &v);
+    }
+    if c.max_duplicate_lines.is_some() {
+        opts.max_duplicate_lines = Limit(c.max_duplicate_lines);
+    }
+    if c.max_duplication_percent.is_some() {
+        opts.max_duplication_percent = Limit(c.max_duplication_percent);
+    }
+    if c.max_clones.is_some() {
+        opts.max_clones = Limit(c.max_clones);
+    }
+    if let Some(v) = c.fail_on_new {
+        opts.fail_on_new = v;
+    }
+    if let Some(v) = c.fail_on_stale_ignores {
+        opts.fail_on_stale_ignores = v;
+    }
+
+    for e in &c.exclude {
+        opts.exclude_patterns.push(absolute_pattern(&dir, e)?);
+    }
+
+    for (glob, settings) in c.paths {
+        let settings = PathOverride::deserialize(settings)
+            .map_err(|e| format!("{}: [paths.\"{}\"] {}", file.display(), glob, e))?;
+        opts.path_rules.push(PathRule {
+            pattern: absolute_pattern(&dir, &glob)?,
+            settings,
+        });
+    }
+
+    Ok(Lists {
+        files: c.files.iter().map(|f| absolute_glob(&dir, f)).collect(),
+        outputs: c
+            .output
+            .iter()
+            .map(|o| match o.split_once('=') {
+                Some((f, p)) if p != "-" => format!("{}={}", f, resolve(&dir, p)),
+                _ => o.clone(),
+            })
+            .collect(),
+        dir: Some(dir),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(a: &[&str]) -> Vec<String> {
+        std::iter::once("duplihere")
+            .chain(a.iter().copied())
+            .map(String::from)
+            .collect()
+    }
+
+    #[test]
+    fn requested_file() {
+        assert_eq!(
+            requested(&args(&["-l", "4", "--config", "a.toml"])),
+            Some(PathBuf::from("a.toml"))
+        );
+        assert_eq!(
+            requested(&args(&["--config=b.toml", "-p"])),
+            Some(PathBuf::from("b.toml"))
+        );
+        assert_eq!(
+            requested(&args(&["--config", "a.toml", "--config=b.toml"])),
+            Some(PathBuf::from("b.toml"))
+        );
+        assert_eq!(
+            requested(&args(&["--config", "a.toml", "--no-config"])),
+            None
+        );
+        assert_eq!(requested(&args(&["--no-config", "-f", "*.c"])), None);
+    }
+
+    #[test]
+    fn requested_stops_at_double_dash() {
+        let rc = requested(&args(&["--config", "a.toml", "--", "--no-config"]));
+        assert_eq!(rc, Some(PathBuf::from("a.toml")));
+    }
+
+    #[test]
+    fn unknown_keys_are_rejected() {
+        assert!(toml::from_str::<ConfigFile>("lines = 4\nprint = true\n").is_ok());
+        assert!(toml::from_str::<ConfigFile>("line = 4\n").is_err());
+        assert!(toml::from_str::<ConfigFile>("lines = \"four\"\n").is_err());
+
+        let c: ConfigFile = toml::from_str("[paths.\"tests/**\"]\nlines = 20\n").unwrap();
+        let p = PathOverride::deserialize(c.paths["tests/**"].clone()).unwrap();
+        assert_eq!(p.lines, Some(20));
+        let c: ConfigFile = toml::from_str("[paths.\"tests/**\"]\nline = 20\n").unwrap();
+        assert!(PathOverride::deserialize(c.paths["tests/**"].clone()).is_err());
+    }
+
+    #[test]
+    fn apply_resolves_paths() {
+        let dir = std::env::temp_dir().join(format!("duplihere-config-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let file = dir.join(CONFIG_FILE);
+        fs::write(
+            &file,
+            "files = [\"src/**/*.c\", \"/abs/*.c\"]\nlines = 9\nfail_on_new = true\n\
+             baseline = \"base.json\"\noutput = [\"text\", \"sarif=out.sarif\", \"json=-\"]\n\
+             max_clones = 3\n[paths.\"vendor/**\"]\nexclude = true\n",
+        )
+        .unwrap();
+
+        let mut opts = Options::default();
+        let lists = apply(&file, &mut opts).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        let dir = fs::canonicalize(std::env::temp_dir())
+            .unwrap()
+            .join(format!("duplihere-config-{}", std::process::id()));
+        let d = dir.to_string_lossy();
+        assert_eq!(opts.lines, 9);
+        assert!(opts.fail_on_new);
+        assert_eq!(opts.max_clones, Limit(Some(3)));
+        assert_eq!(opts.ignore, format!("{}/base.json", d));
+        assert_eq!(
+            lists.files,
+            vec![format!("{}/src/**/*.c", d), "/abs/*.c".to_string()]
+        );
+        assert_eq!(
+            lists.outputs,
+            vec![
+                "text".to_string(),
+                format!("sarif={}/out.sarif", d),
+                "json=-".to_string()
+            ]
+        );
+        assert_eq!(opts.path_rules.len(), 1);
+        assert!(opts.path_rules[0]
+            .pattern
+            .matches(&format!("{}/vendor/x.c", d)));
+    }
+
+    #[test]
+    fn files_relative_to_a_directory_with_wildcards() {
+        let dir = std::env::temp_dir().join(format!("duplihere-[config]-{}", std::process::id()));
+        fs::create_dir_all(dir.join("src")).unwrap();
+        fs::write(dir.join("src/a.c"), "").unwrap();
+        let file = dir.join(CONFIG_FILE);
+        fs::write(&file, "files = [\"src/*.c\"]\n").unwrap();
+
+        let lists = apply(&file, &mut Options::default()).unwrap();
+        let found: Vec<PathBuf> = glob::glob(&lists.files[0]).unwrap().flatten().collect();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(found.len(), 1);
+        assert!(found[0].ends_with("src/a.c"));
+    }
+}
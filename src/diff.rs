@@ -0,0 +1,255 @@
+// SPDX-License-Identifier: GPL-3.0-only
+//
+// Copyright (C) 2019-2023 Tony Asleson <tony.asleson@gmail.com>
+
+//! Lines changed by a unified diff, for `--diff` and `--git-diff`, so that only duplicates
+//! which touch new or modified lines are reported.
+
+use std::collections::HashMap;
+use std::fs::{canonicalize, read_to_string};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::process;
+
+/// Changed lines by canonical file name, as 0-based line ranges [start, end).
+pub type ChangedLines = HashMap<String, Vec<(u32, u32)>>;
+
+/// Read a patch file, `-` is stdin.
+pub fn read_patch(file_name: &str) -> Result<String, String> {
+    if file_name == "-" {
+        let mut s = String::new();
+        io::stdin()
+            .read_to_string(&mut s)
+            .map_err(|e| format!("Unable to read diff from stdin, reason {}", e))?;
+        Ok(s)
+    } else {
+        read_to_string(file_name)
+            .map_err(|e| format!("Unable to read diff {}, reason {}", file_name, e))
+    }
+}
+
+/// The lines changed between `base` and the working tree from git.  Files git doesn't know
+/// about yet aren't in the diff, those which aren't ignored are changed in full.
+pub fn git_changes(base: &str) -> Result<ChangedLines, String> {
+    let top = PathBuf::from(git(&["rev-parse", "--show-toplevel"])?.trim());
+    let diff = git(&["diff", "-U0", "--no-color", "--no-ext-diff", base, "--"])?;
+    let untracked = git(&[
+        "ls-files",
+        "--others",
+        "--exclude-standard",
+        "--full-name",
+        "-z",
+        "--",
+        ":/",
+    ])?;
+
+    let bases = [top];
+    let mut rc = parse(&diff, &bases);
+    for f in untracked.split('\0').filter_map(|p| resolve(p, &bases)) {
+        rc.insert(f, vec![(0, u32::MAX)]);
+    }
+    Ok(rc)
+}
+
+fn git(args: &[&str]) -> Result<String, String> {
+    let out = process::Command::new("git")
+        .args(args)
+        .output()
+        .map_err(|e| format!("Unable to run git, reason {}", e))?;
+    if !out.status.success() {
+        return Err(format!(
+            "git {} failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&out.stderr).trim()
+        ));
+    }
+    Ok(String::from_utf8_lossy(&out.stdout).to_string())
+}
+
+/// The start line and count of a range in a hunk header, `a,b` or just `a` when the count is 1.
+fn hunk_range(range: &str) -> Option<(u32, u32)> {
+    let mut parts = range.splitn(2, ',');
+    let start = parts.next()?.parse().ok()?;
+    let count = match parts.next() {
+        Some(c) => c.parse().ok()?,
+        None => 1,
+    };
+    Some((start, count))
+}
+
+/// The new file start line and the number of old and new lines from a hunk header,
+/// `@@ -a,b +c,d @@`.
+fn hunk_header(line: &str) -> Option<(u32, u32, u32)> {
+    let mut fields = line.split_whitespace().skip(1);
+    let (_, old) = hunk_range(fields.next()?.strip_prefix('-')?)?;
+    let (start, new) = hunk_range(fields.next()?.strip_prefix('+')?)?;
+    Some((start, old, new))
+}
+
+/// The path of the new file from a `+++ ` line, trailing timestamps and git quoting removed.
+fn new_path(line: &str) -> Option<&str> {
+    let path = line.split('\t').next()?.trim_end();
+    let path = path
+        .strip_prefix('"')
+        .and_then(|p| p.strip_suffix('"'))
+        .unwrap_or(path);
+    if path == "/dev/null" {
+        None
+    } else {
+        Some(path)
+    }
+}
+
+/// Find the file a diff refers to, trying the path as is and without the leading `b/`
+/// style component, relative to each of the base directories in turn.
+fn resolve(path: &str, bases: &[PathBuf]) -> Option<String> {
+    let p = Path::new(path);
+    let mut candidates = vec![p];
+    let mut components = p.components();
+    if components.next().is_some() && components.clone().next().is_some() {
+        candidates.push(components.as_path());
+    }
+
+    for c in candidates {
+        for b in bases {
+            if let Ok(f) = canonicalize(b.join(c)) {
+                if f.is_file() {
+                    return f.to_str().map(|s| s.to_string());
+                }
+            }
+        }
+    }
+    None
+}
+
+/// Parse a unified diff, recording the lines added or modified in each file.  Files which
+/// were deleted, or which we can't find relative to `bases`, are left out.
+pub fn parse(text: &str, bases: &[PathBuf]) -> ChangedLines {
+    let mut rc = ChangedLines::new();
+    let mut file: Option<String> = None;
+    let mut line_number = 0;
+    // Lines of the current hunk still to come from the old and new file.
+    let mut old_left: u32 = 0;
+    let mut new_left: u32 = 0;
+
+    for l in text.lines() {
+        if old_left > 0 || new_left > 0 {
+            match l.as_bytes().first() {
+                Some(b'+') => {
+                    if let Some(f) = &file {
+                        let ranges = rc.entry(f.clone()).or_default();
+                        match ranges.last_mut() {
+                            Some(r) if r.1 == line_number => r.1 += 1,
+                            _ => ranges.push((line_number, line_number + 1)),
+                        }
+                    }
+                    line_number += 1;
+                    new_left = new_left.saturating_sub(1);
+                }
+                Some(b'-') => old_left = old_left.saturating_sub(1),
+                Some(b'\\') => {}
+                _ => {
+                    line_number += 1;
+                    old_left = old_left.saturating_sub(1);
+                    new_left = new_left.saturating_sub(1);
+                }
+            }
+        } else if let Some(p) = l.strip_prefix("+++ ") {
+            file = new_path(p).and_then(|p| resolve(p, bases));
+            if file.is_none() {
+                if let Some(p) = new_path(p) {
+                    eprintln!("WARNING: Unable to find {} from the diff", p);
+                }
+            }
+        } else if let Some((start, old, new)) = l.strip_prefix("@@ ").and_then(|_| hunk_header(l)) {
+            // Line numbers are 1-based, a start of 0 is a hunk which only removes lines.
+            line_number = start.saturating_sub(1);
+            old_left = old;
+            new_left = new;
+        }
+    }
+    rc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn hunk_headers() {
+        assert_eq!(hunk_range("12,3"), Some((12, 3)));
+        assert_eq!(hunk_range("7"), Some((7, 1)));
+        assert_eq!(hunk_range("x,3"), None);
+        assert_eq!(hunk_header("@@ -1,2 +3,4 @@ fn main()"), Some((3, 2, 4)));
+        assert_eq!(hunk_header("@@ -10 +10,0 @@"), Some((10, 1, 0)));
+        assert_eq!(hunk_header("@@ bogus @@"), None);
+    }
+
+    #[test]
+    fn new_paths() {
+        assert_eq!(new_path("b/src/main.rs"), Some("b/src/main.rs"));
+        assert_eq!(
+            new_path("new.c\t2023-09-01 14:03:27.000000000 +0200"),
+            Some("new.c")
+        );
+        assert_eq!(new_path("\"b/with space.c\""), Some("b/with space.c"));
+        assert_eq!(new_path("/dev/null"), None);
+    }
+
+    #[test]
+    fn changed_lines() {
+        let dir = std::env::temp_dir().join(format!("duplihere-diff-{}", std::process::id()));
+        fs::create_dir_all(dir.join("src")).unwrap();
+        for f in &["src/a.c", "b.c", "c.c"] {
+            fs::write(dir.join(f), "").unwrap();
+        }
+        let a = canonicalize(dir.join("src/a.c"))
+            .unwrap()
+            .to_string_lossy()
+            .to_string();
+        let b = canonicalize(dir.join("b.c"))
+            .unwrap()
+            .to_string_lossy()
+            .to_string();
+
+        let patch = "\
+diff --git a/src/a.c b/src/a.c
+--- a/src/a.c
++++ b/src/a.c
+@@ -2,3 +2,4 @@
+ kept
+-old
++new
++added
+ kept
+@@ -20,2 +21,0 @@
+-removed
+-removed
+@@ -30,0 +30,2 @@
++x
++y
+\\ No newline at end of file
+--- b.c
++++ b.c
+@@ -0,0 +1,1 @@
++only
+--- c.c
++++ /dev/null
+@@ -1 +0,0 @@
+-gone
+--- missing.c
++++ missing.c
+@@ -1 +1 @@
+-a
++b
+";
+        let rc = parse(patch, std::slice::from_ref(&dir));
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(rc.len(), 2);
+        // 0-based, [start, end).
+        assert_eq!(rc[&a], vec![(2, 4), (29, 31)]);
+        assert_eq!(rc[&b], vec![(0, 1)]);
+    }
+}
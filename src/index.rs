@@ -0,0 +1,72 @@
+// SPDX-License-Identifier: GPL-3.0-only
+//
+// Copyright (C) 2019-2023 Tony Asleson <tony.asleson@gmail.com>
+
+//! Prebuilt index of a reference corpus, `index build`, so new code can be checked against it
+//! with `scan --against` without hashing the corpus every time.
+
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::BufReader;
+
+use crate::{hash_algorithm, save_bincode, LineId, Normalize};
+
+/// Bump this whenever the layout of the index file changes.
+const INDEX_VERSION: u32 = 1;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Index {
+    index_version: u32,
+    hash_algorithm: String,
+    pub normalize: Normalize,
+    /// Window size of the rolling hashes, which is the minimum number of duplicate lines.
+    pub lines: u32,
+    /// Canonical names of the corpus files, a file's position is its id.
+    pub files: Vec<String>,
+    /// Line signatures of each file.
+    pub signatures: Vec<Vec<u64>>,
+    /// Line ranges of each file suppressed by inline markers.
+    pub suppressed: Vec<Vec<(u32, u32)>>,
+    /// Every rolling hash with the places it occurs.
+    pub table: Vec<(u64, Vec<LineId>)>,
+}
+
+impl Index {
+    pub fn new(lines: u32, normalize: Normalize) -> Index {
+        Index {
+            index_version: INDEX_VERSION,
+            hash_algorithm: hash_algorithm(),
+            normalize,
+            lines,
+            files: vec![],
+            signatures: vec![],
+            suppressed: vec![],
+            table: vec![],
+        }
+    }
+
+    /// Load an index, which has to have been made by this version of duplihere.
+    pub fn load(file_name: &str) -> Result<Index, String> {
+        let f = File::open(file_name)
+            .map_err(|e| format!("Unable to open index {}, reason {}", file_name, e))?;
+        let i: Index = bincode::deserialize_from(BufReader::new(f))
+            .map_err(|e| format!("Unable to read index {}, reason {}", file_name, e))?;
+
+        if i.index_version != INDEX_VERSION || i.hash_algorithm != hash_algorithm() {
+            return Err(format!(
+                "Index {} was built by a different version of duplihere, rebuild it",
+                file_name
+            ));
+        }
+        Ok(i)
+    }
+
+    pub fn save(&self, file_name: &str) -> Result<(), String> {
+        save_bincode(self, file_name, "index")
+    }
+
+    /// Total number of lines in the corpus.
+    pub fn lines_indexed(&self) -> u64 {
+        self.signatures.iter().map(|s| s.len() as u64).sum()
+    }
+}
@@ -6,23 +6,40 @@ extern crate lazy_static;
 
 extern crate dashmap;
 extern crate rags_rs as rags;
+
+mod baseline;
+mod cache;
+mod compare;
+mod config;
+mod diff;
+mod index;
+mod output;
+mod report;
+mod snippet;
+
 use glob::glob;
 use rags::argparse;
 use rayon::prelude::*;
 
-use serde::ser::SerializeStruct;
-use serde::{Serialize, Serializer};
-
-use std::collections::{hash_map::DefaultHasher, HashMap, VecDeque};
+use std::collections::{hash_map::DefaultHasher, HashMap, HashSet, VecDeque};
+use std::fmt;
 use std::fs::{canonicalize, File};
 use std::hash::{Hash, Hasher};
 use std::io::{prelude::*, BufReader};
+use std::path::{Path, PathBuf};
 use std::process;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex};
 
 use dashmap::mapref::entry::Entry;
 use dashmap::DashMap;
 
+/// Exit codes, like grep, 0 when there is nothing to report, 1 when a quality gate failed, eg.
+/// `--max-clones`, and 2 for any error.
+const EXIT_GATE: i32 = 1;
+const EXIT_ERROR: i32 = 2;
+
 lazy_static! {
     static ref FILE_LOOKUP: Mutex<FileId> = Mutex::new(FileId::new());
 }
@@ -34,38 +51,216 @@ fn calculate_hash<T: Hash>(t: T) -> u64 {
     s.finish()
 }
 
+/// Identifies how signatures are made, for anything which stores them between runs.  The std
+/// `DefaultHasher` isn't guaranteed to be stable between rust releases, so along with the
+/// version of duplihere it includes the hashes of some fixed input, which change with it.
+fn hash_algorithm() -> String {
+    format!(
+        "DefaultHasher/{}/{:016x}{:016x}",
+        env!("CARGO_PKG_VERSION"),
+        calculate_hash("duplihere hash probe"),
+        signatures_key(&[1, 2, 3])
+    )
+}
+
+/// Write `value` to a file with bincode, going through a temporary file which is renamed into
+/// place so an interrupted run can't leave a truncated file behind.  `what` is the kind of file
+/// for the error message.
+fn save_bincode<T: serde::Serialize>(value: &T, file_name: &str, what: &str) -> Result<(), String> {
+    let err =
+        |e: &dyn fmt::Display| format!("Unable to write {} {}, reason {}", what, file_name, e);
+    let tmp = format!("{}.tmp", file_name);
+    let f = File::create(&tmp).map_err(|e| err(&e))?;
+    let mut w = std::io::BufWriter::new(f);
+    bincode::serialize_into(&mut w, value).map_err(|e| err(&e))?;
+    w.flush().map_err(|e| err(&e))?;
+    std::fs::rename(&tmp, file_name).map_err(|e| err(&e))
+}
+
+/// How lines of text are normalized before their signature is calculated.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Normalize {
+    /// Leading and trailing white space is removed.
+    Trim,
+    /// In addition runs of white space within the line are treated as a single space.
+    Whitespace,
+}
+
+impl FromStr for Normalize {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "trim" => Ok(Normalize::Trim),
+            "whitespace" => Ok(Normalize::Whitespace),
+            _ => Err(format!(
+                "unknown value '{}', expected one of trim, whitespace",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Normalize {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Normalize::Trim => "trim",
+            Normalize::Whitespace => "whitespace",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+/// Set once from the options before any files are processed, see `Normalize`.
+static COLLAPSE_WHITESPACE: AtomicBool = AtomicBool::new(false);
+
+/// The signature of a single line of text, leading and trailing white space is not significant.
+fn line_signature(line: &str) -> u64 {
+    if COLLAPSE_WHITESPACE.load(Ordering::Relaxed) {
+        calculate_hash(line.split_whitespace().collect::<Vec<&str>>().join(" "))
+    } else {
+        calculate_hash(line.trim())
+    }
+}
+
+/// The key for a run of lines, given their signatures.  This is the same value that
+/// `maximize_collision` calculates as it walks the matching lines.
+fn signatures_key(signatures: &[u64]) -> u64 {
+    let mut s = DefaultHasher::new();
+    for sig in signatures {
+        sig.hash(&mut s);
+    }
+    s.finish()
+}
+
+/// Inline markers, in a comment of any syntax, which stop duplicates being reported.
+/// `duplihere:ignore-next <n>` covers the following n lines and `duplihere:off` covers everything
+/// up to and including a following `duplihere:on`, or the end of the file.
+const MARKER: &str = "duplihere:";
+
+/// The ways a comment can start, a marker has to come straight after one.
+const COMMENT_LEADERS: [&str; 6] = ["//", "#", "/*", "--", "<!--", ";"];
+
+#[derive(Debug, PartialEq, Eq)]
+enum Marker {
+    IgnoreNext(u32),
+    Off,
+    On,
+}
+
+/// Look for a suppression marker in a line of text, an error is a marker we can't make sense of.
+/// Only a line which is a comment starting with the marker counts, so one mentioned in code, a
+/// string or the text of a comment is left alone.
+fn parse_marker(line: &str) -> Result<Option<Marker>, String> {
+    let (before, rest) = match line.find(MARKER) {
+        Some(i) => (line[..i].trim_end(), &line[i + MARKER.len()..]),
+        None => return Ok(None),
+    };
+    if !COMMENT_LEADERS.iter().any(|l| before.ends_with(l))
+        || !before
+            .chars()
+            .all(|c| c.is_whitespace() || c.is_ascii_punctuation())
+        || before.contains(['`', '"', '\''])
+    {
+        return Ok(None);
+    }
+    let word = |w: &str| {
+        rest.strip_prefix(w)
+            .filter(|r| !r.starts_with(|c: char| c.is_alphanumeric() || c == '-' || c == '_'))
+    };
+
+    if let Some(n) = word("ignore-next") {
+        let n = n.trim_start();
+        let digits = n.find(|c: char| !c.is_ascii_digit()).unwrap_or(n.len());
+        n[..digits]
+            .parse()
+            .map(|n| Some(Marker::IgnoreNext(n)))
+            .map_err(|_| format!("{}ignore-next needs a number of lines", MARKER))
+    } else if word("off").is_some() {
+        Ok(Some(Marker::Off))
+    } else if word("on").is_some() {
+        Ok(Some(Marker::On))
+    } else {
+        Ok(None)
+    }
+}
+
 /// For a given file, walk it line by line calculating, removing leading and trailing WS and
 /// calculating the signatures for each line, return the information as a vector of hash signatures.
-fn file_signatures(filename: &str) -> Vec<u64> {
-    let file = match File::open(filename) {
-        Ok(file) => file,
+/// We also return the line ranges, [start, end), which are suppressed by inline markers.
+fn file_signatures(filename: &str) -> (Vec<u64>, Vec<(u32, u32)>) {
+    match File::open(filename) {
+        Ok(file) => signatures(BufReader::new(file), filename),
         Err(e) => {
             eprintln!("ERROR: Unable to open {}, reason {}", filename, e);
-            return Vec::new();
+            (Vec::new(), Vec::new())
         }
-    };
+    }
+}
 
+/// The line signatures and suppressed line ranges for text from any reader, `filename` is
+/// only used for messages.
+fn signatures<R: BufRead>(mut reader: R, filename: &str) -> (Vec<u64>, Vec<(u32, u32)>) {
     let mut rc: Vec<u64> = Vec::new();
-    let mut reader = BufReader::new(file);
+    let mut suppressed: Vec<(u32, u32)> = Vec::new();
+    let mut off_start: Option<u32> = None;
     let mut buf: Vec<u8> = vec![];
 
     loop {
         match reader.read_until(b'\n', &mut buf) {
             Ok(num_bytes) => {
                 if num_bytes == 0 {
-                    return rc;
+                    break;
                 } else {
                     let l = String::from_utf8_lossy(&buf);
-                    rc.push(calculate_hash(l.trim()));
+                    let line_number = rc.len() as u32;
+                    match parse_marker(&l) {
+                        Ok(Some(Marker::IgnoreNext(n))) => suppressed.push((
+                            line_number + 1,
+                            line_number.saturating_add(1).saturating_add(n),
+                        )),
+                        Ok(Some(Marker::Off)) => {
+                            off_start.get_or_insert(line_number);
+                        }
+                        Ok(Some(Marker::On)) => {
+                            if let Some(start) = off_start.take() {
+                                suppressed.push((start, line_number + 1));
+                            }
+                        }
+                        Ok(None) => {}
+                        Err(e) => {
+                            eprintln!("WARNING: {}:{}: {}", filename, line_number + 1, e)
+                        }
+                    }
+                    rc.push(line_signature(&l));
                     buf.clear();
                 }
             }
             Err(e) => {
                 eprintln!("WARNING: Error processing file {} reason {}", filename, e);
-                return rc;
+                break;
             }
         }
     }
+
+    if let Some(start) = off_start {
+        suppressed.push((start, u32::MAX));
+    }
+    (rc, merge_ranges(suppressed))
+}
+
+/// Sort line ranges, [start, end), joining those which overlap or are next to each other.
+fn merge_ranges(mut ranges: Vec<(u32, u32)>) -> Vec<(u32, u32)> {
+    ranges.sort_unstable();
+    let mut rc: Vec<(u32, u32)> = Vec::with_capacity(ranges.len());
+    for (s, e) in ranges {
+        match rc.last_mut() {
+            Some(last) if s <= last.1 => last.1 = last.1.max(e),
+            _ => rc.push((s, e)),
+        }
+    }
+    rc
 }
 
 /// For a specific file, calculate the hash signature for 'min_lines' in size using a sliding window
@@ -93,28 +288,118 @@ fn rolling_hashes(file_signatures: &[u64], min_lines: usize) -> Vec<(u64, u32)>
     rc
 }
 
-fn process_file(
-    file_id: u32,
-    filename: &str,
-    min_lines: usize,
-    file_hashes: &Mutex<Vec<Vec<u64>>>,
-    collision_hashes: &DashMap<u64, Vec<LineId>>,
-) {
-    let file_signatures = file_signatures(filename);
-    let file_rolling_hashes = rolling_hashes(&file_signatures, min_lines);
+/// What is gathered while hashing the files of a scan, the signatures and suppressed line
+/// ranges of each file by file id, and the places each rolling hash occurs.
+struct Hashing {
+    file_hashes: Mutex<Vec<Vec<u64>>>,
+    file_suppressions: Mutex<Vec<Vec<(u32, u32)>>>,
+    collision_hashes: DashMap<u64, Vec<LineId>>,
+    cache: Option<cache::Cache>,
+    min_lines: u32,
+    /// Only the places of the hashes which were there to start with are added, so just the
+    /// duplicates of what is already known are found.
+    known_only: bool,
+}
+
+impl Hashing {
+    fn new(opts: &Options, num_files: usize, min_lines: u32) -> Hashing {
+        Hashing {
+            file_hashes: Mutex::new(vec![vec![]; num_files]),
+            file_suppressions: Mutex::new(vec![vec![]; num_files]),
+            collision_hashes: DashMap::new(),
+            cache: load_cache(opts, min_lines),
+            min_lines,
+            known_only: false,
+        }
+    }
+
+    /// Start from files which have already been hashed, eg. a snippet or an index, and only
+    /// look for duplicates of them.
+    fn known(
+        opts: &Options,
+        file_hashes: Vec<Vec<u64>>,
+        file_suppressions: Vec<Vec<(u32, u32)>>,
+        collision_hashes: DashMap<u64, Vec<LineId>>,
+        min_lines: u32,
+    ) -> Hashing {
+        Hashing {
+            file_hashes: Mutex::new(file_hashes),
+            file_suppressions: Mutex::new(file_suppressions),
+            collision_hashes,
+            cache: load_cache(opts, min_lines),
+            min_lines,
+            known_only: true,
+        }
+    }
+
+    /// Hash the files in parallel, then write the cache when there is one.
+    fn process_files(&mut self, files: &[(u32, Arc<String>)], opts: &Options) {
+        files
+            .par_iter()
+            .for_each(|(file_id, name)| self.process_file(*file_id, name));
+        save_cache(self.cache.take(), opts, files);
+    }
+
+    /// Calculate the signatures and rolling hashes for a file, or get them from the cache, and
+    /// register them in the collision hash.
+    fn process_file(&self, file_id: u32, filename: &str) {
+        let (file_signatures, suppressed, file_rolling_hashes) = match &self.cache {
+            Some(c) => c.file(filename),
+            None => {
+                let (file_signatures, suppressed) = file_signatures(filename);
+                let rolling = rolling_hashes(&file_signatures, self.min_lines as usize);
+                (file_signatures, suppressed, rolling)
+            }
+        };
 
-    file_hashes.lock().unwrap()[file_id as usize] = file_signatures;
+        self.file_hashes.lock().unwrap()[file_id as usize] = file_signatures;
+        if !suppressed.is_empty() {
+            self.file_suppressions.lock().unwrap()[file_id as usize] = suppressed;
+        }
 
-    for e in file_rolling_hashes {
-        let (r_hash, line_number) = e;
-        collision_hashes
-            .entry(r_hash)
-            .or_insert_with(|| Vec::with_capacity(1))
-            .push(LineId {
+        for (r_hash, line_number) in file_rolling_hashes {
+            let line_id = LineId {
                 file_id,
                 line_number,
-            });
+            };
+            if self.known_only {
+                if let Some(mut v) = self.collision_hashes.get_mut(&r_hash) {
+                    v.push(line_id);
+                }
+            } else {
+                self.collision_hashes
+                    .entry(r_hash)
+                    .or_insert_with(|| Vec::with_capacity(1))
+                    .push(line_id);
+            }
+        }
     }
+
+    /// The files, with the rest of what the report needs left for the caller to fill in, and
+    /// the collision hash.
+    fn finish(self) -> (ScannedFiles, DashMap<u64, Vec<LineId>>) {
+        let files = ScannedFiles {
+            file_hashes: self.file_hashes.into_inner().unwrap(),
+            file_suppressions: self.file_suppressions.into_inner().unwrap(),
+            file_min_lines: vec![],
+            file_changes: None,
+            stats: Default::default(),
+        };
+        (files, self.collision_hashes)
+    }
+}
+
+/// What is known about the scanned files, by file id, which the report is built from.
+struct ScannedFiles {
+    /// Line signatures.
+    file_hashes: Vec<Vec<u64>>,
+    /// Line ranges suppressed by inline markers, [start, end).
+    file_suppressions: Vec<Vec<(u32, u32)>>,
+    /// Minimum number of duplicate lines for instances.
+    file_min_lines: Vec<u32>,
+    /// Lines changed by a diff, when there is one.
+    file_changes: Option<Vec<Vec<(u32, u32)>>>,
+    stats: report::ScanStats,
 }
 
 /// Used to record a section of duplicated text.  We store the hash signature, how many lines
@@ -127,32 +412,6 @@ struct Collision {
     sig: u64,
 }
 
-/// Used to convert a collision in our results to JSON for it.
-impl Serialize for Collision {
-    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
-    where
-        S: Serializer,
-    {
-        let file_lookup_lock = FILE_LOOKUP.lock().unwrap();
-        let files_infos: Vec<(String, u32)> = self
-            .start_lines
-            .iter()
-            .map(|i| {
-                (
-                    file_lookup_lock.id_to_name(i.file_id).to_string(),
-                    i.line_number,
-                )
-            })
-            .collect();
-
-        let mut fid = serializer.serialize_struct("Collision", 3)?;
-        fid.serialize_field("key", &self.key)?;
-        fid.serialize_field("num_lines", &self.num_lines)?;
-        fid.serialize_field("files", &files_infos)?;
-        fid.end()
-    }
-}
-
 impl Collision {
     /// A signature for a collision is the hash value of the data that represents the collision,
     /// this is used to identify duplicate result collisions, see _signature for calculation.
@@ -222,14 +481,6 @@ impl Collision {
     }
 }
 
-/// Some stats on what we processed and found.
-#[derive(Debug, Serialize)]
-struct ReportResults<'a> {
-    num_lines: u64,
-    num_ignored: u64,
-    duplicates: &'a [Collision],
-}
-
 // Check to see if we are checking for duplicate text in the same file and that one or more lines
 // overlap with each other.  There is nothing useful to report when this occurs, because the same
 // lines of text match each other in the same file.
@@ -279,8 +530,8 @@ fn maximize_collision(
         }
     }
 
-    // If after walking we overlap skip too
-    if overlap(l_info, r_info, offset) {
+    // If after walking we overlap skip too, or the window only matched by hash
+    if offset < min_lines || overlap(l_info, r_info, offset) {
         return None;
     }
 
@@ -293,111 +544,24 @@ fn maximize_collision(
     })
 }
 
-/// Given a file name, a start line number, and number of lines, dump the text into the output.
-fn print_dup_text(filename: &str, start_line: usize, count: usize) {
-    let file = File::open(filename)
-        .unwrap_or_else(|_| panic!("Unable to open file we have already opened {:?}", filename));
-    let mut reader = BufReader::new(file);
-    let mut line_number = 0;
-    let end = start_line + count;
-
-    while line_number < end {
-        let mut buf: Vec<u8> = vec![];
-        match reader.read_until(0xA, &mut buf) {
-            Ok(num_bytes) => {
-                if num_bytes == 0 {
-                    break;
-                } else if line_number >= start_line {
-                    print!("{}", String::from_utf8_lossy(&buf));
-                }
-
-                line_number += 1;
-            }
-            Err(e) => {
-                eprintln!("WARNING: Error processing file {} reason {}", filename, e);
-                break;
-            }
-        }
-    }
-}
-
-/// Display the output as text or structured JSON.
-fn print_report(
-    printable_results: &[Collision],
-    opts: &Options,
-    ignore_hashes: &HashMap<u64, bool>,
-) {
-    let mut num_lines: u64 = 0;
-    let mut ignored: u64 = 0;
-    let file_lookup_locked = FILE_LOOKUP.lock().unwrap();
-
-    for p in printable_results.iter() {
-        if ignore_hashes.contains_key(&p.key) {
-            ignored += 1;
-        } else {
-            num_lines += (p.num_lines as usize * (p.start_lines.len() - 1)) as u64;
-
-            if !opts.json {
-                println!(
-                    "{}\nHash signature = {}\nFound {} copy & pasted lines in the following files:",
-                    "*".repeat(80),
-                    p.key,
-                    p.num_lines
-                );
-
-                for spec_file in &p.start_lines {
-                    let filename = file_lookup_locked.id_to_name(spec_file.file_id);
-                    let start_line = spec_file.line_number;
-                    let end_line = start_line + p.num_lines;
-                    println!(
-                        "Between lines {} and {} in {}",
-                        start_line + 1,
-                        end_line,
-                        filename
-                    );
-                }
-
-                if opts.print {
-                    print_dup_text(
-                        &file_lookup_locked.id_to_name(p.start_lines[0usize].file_id),
-                        p.start_lines[0usize].line_number as usize,
-                        p.num_lines as usize,
-                    );
-                }
-            }
-        }
-    }
-
-    if !opts.json {
-        println!(
-            "Found {} duplicate lines in {} chunks in {} files, {} chunks ignored.\n\
-            https://github.com/tasleson/duplihere",
-            num_lines,
-            printable_results.len() - ignored as usize,
-            file_lookup_locked.number_files(),
-            ignored
-        )
-    } else {
-        let r = ReportResults {
-            num_lines,
-            num_ignored: ignored,
-            duplicates: printable_results,
-        };
-        println!("{}", serde_json::to_string_pretty(&r).unwrap());
-    }
-}
-
 /// When we have more than one region of text that matches another we will walk all combination
 /// of matching text and see if we actually have a bigger overlap of texts.  When we do we will
-/// store in in the results hash.
+/// store in in the results hash.  When files are in sets, eg. `--left` and `--right`, text
+/// matching other text in the same set is of no interest.
 fn walk_collision(
     collisions: &[LineId],
     file_hashes: &[Vec<u64>],
+    file_groups: &[u32],
     min_lines: u32,
     results_hash: &DashMap<u64, Collision>,
 ) {
     for (i, l_id) in collisions[0..(collisions.len() - 1)].iter().enumerate() {
         for r_id in &collisions[i + 1..] {
+            if !file_groups.is_empty()
+                && file_groups[l_id.file_id as usize] == file_groups[r_id.file_id as usize]
+            {
+                continue;
+            }
             if let Some(coll) = maximize_collision(file_hashes, l_id, r_id, min_lines) {
                 match results_hash.entry(coll.key) {
                     Entry::Occupied(mut o) => o.get_mut().start_lines.extend(coll.start_lines),
@@ -418,7 +582,8 @@ fn walk_collision(
 fn find_collisions(
     collision_hash: DashMap<u64, Vec<LineId>>,
     file_hashes: &mut [Vec<u64>],
-    opts: &Options,
+    file_groups: &[u32],
+    min_lines: u32,
 ) -> DashMap<u64, Collision> {
     let results_hash: DashMap<u64, Collision> = DashMap::new();
 
@@ -436,18 +601,34 @@ fn find_collisions(
 
     collision_vec
         .par_iter()
-        .for_each(|e| walk_collision(e, file_hashes, opts.lines, &results_hash));
+        .for_each(|e| walk_collision(e, file_hashes, file_groups, min_lines, &results_hash));
 
     results_hash
 }
 
+/// Does the instance of `num_lines` starting at `l` overlap any of the line ranges,
+/// [start, end).
+fn touches(ranges: &[(u32, u32)], l: &LineId, num_lines: u32) -> bool {
+    let end = l.line_number + num_lines;
+    ranges.iter().any(|&(s, e)| s < end && l.line_number < e)
+}
+
+/// Is the instance of `num_lines` starting at `l` entirely in one of the line ranges,
+/// [start, end).
+fn within(ranges: &[(u32, u32)], l: &LineId, num_lines: u32) -> bool {
+    let end = l.line_number + num_lines;
+    ranges.iter().any(|&(s, e)| s <= l.line_number && end <= e)
+}
+
 /// We have all the data, we now need to do some sorting and duplicate removals and then
-/// dump the end data.
+/// build the report from the end data.
 fn process_report(
     results_hash: DashMap<u64, Collision>,
+    files: &ScannedFiles,
     opts: &Options,
-    ignore_hashes: &HashMap<u64, bool>,
-) {
+    ignores: &baseline::Matcher,
+) -> report::Report {
+    let mut stats = files.stats;
     let mut final_report: Vec<Collision> = results_hash.into_iter().map(|(_, v)| v).collect();
     final_report.par_sort_unstable_by(|a, b| a.num_lines.cmp(&b.num_lines).reverse());
 
@@ -467,6 +648,28 @@ fn process_report(
         }
     }
 
+    // Drop the instances which are inside regions suppressed by inline markers, a duplicate needs
+    // at least two instances left to be worth reporting.  Files can also have their own minimum
+    // number of lines from the configuration file.
+    printable_results.retain_mut(|ea| {
+        let before = ea.start_lines.len();
+        let num_lines = ea.num_lines;
+        ea.start_lines
+            .retain(|l| !within(&files.file_suppressions[l.file_id as usize], l, num_lines));
+        let removed = before - ea.start_lines.len();
+        stats.suppressed_instances += removed as u64;
+        if ea.start_lines.len() < 2 {
+            if removed > 0 {
+                stats.suppressed_clones += 1;
+            }
+            return false;
+        }
+
+        ea.start_lines
+            .retain(|l| num_lines >= files.file_min_lines[l.file_id as usize]);
+        ea.start_lines.len() >= 2
+    });
+
     printable_results.par_sort_unstable_by(|a, b| {
         a.num_lines
             .cmp(&b.num_lines)
@@ -478,46 +681,90 @@ fn process_report(
             .then_with(|| a.start_lines[0].file_id.cmp(&b.start_lines[0].file_id))
     });
 
-    print_report(&printable_results, opts, ignore_hashes);
-}
-
-/// Open the user supplied file which contains the hash signatures for text that we don't
-/// want to report on.
-fn get_ignore_hashes(file_name: &str) -> HashMap<u64, bool> {
-    let mut ignores: HashMap<u64, bool> = HashMap::new();
-
-    let fh = File::open(file_name);
+    // With a diff only the duplicates which touch a changed line are of interest, everything
+    // was still scanned so that new code copied from old code is found.  The others are still
+    // matched against the baseline, so their entries aren't stale.
+    let in_diff: Option<Vec<bool>> = files.file_changes.as_ref().map(|changes| {
+        printable_results
+            .iter()
+            .map(|ea| {
+                ea.start_lines
+                    .iter()
+                    .any(|l| touches(&changes[l.file_id as usize], l, ea.num_lines))
+            })
+            .collect()
+    });
 
-    match fh {
-        Ok(fh) => {
-            let buf = BufReader::new(fh);
+    report::Report::new(
+        &printable_results,
+        in_diff.as_deref(),
+        opts,
+        &files.file_hashes,
+        ignores,
+        stats,
+    )
+}
 
-            for line in buf.lines() {
-                let t = line.unwrap();
-                let l = t.trim();
+/// Write the report to each of the requested outputs, or the side by side view for `show`.
+fn write_report(r: &mut report::Report, opts: &Options) {
+    let render = output::RenderOptions {
+        print: opts.print,
+        print_instances: opts.print_instances,
+        context: opts.context,
+        color: opts.color,
+        depth: opts.depth.0,
+    };
 
-                if !l.is_empty() && !l.starts_with('#') {
-                    if let Ok(hv) = l.parse::<u64>() {
-                        ignores.insert(hv, true);
-                    } else {
-                        eprintln!("WARNING: Ignore file contains invalid hash value \"{}\"", l);
-                    }
-                }
+    let result = if opts.subcmds.iter().any(|s| s == "show") {
+        if !opts.show_keys.is_empty() {
+            r.duplicates.retain(|d| opts.show_keys.contains(&d.key));
+            if r.duplicates.is_empty() {
+                eprintln!("No duplicates found with the supplied hash signature(s)");
             }
         }
-        Err(e) => {
-            eprintln!(
-                "Unable to open supplied ignore file {}, reason: {}",
-                file_name, e
-            );
-            process::exit(2);
+        let show = output::ShowReporter {
+            width: opts.width,
+            color: opts.color.enabled(true),
+        };
+        let stdout = std::io::stdout();
+        let mut lock = stdout.lock();
+        output::Reporter::write(&show, r, &mut lock)
+    } else {
+        // Only the formats which include the text need it read up front.
+        if opts.print && opts.outputs.iter().any(|o| o.format.includes_text()) {
+            r.read_snippets();
         }
+        output::write_reports(r, &opts.outputs, &render)
+    };
+
+    if let Err(e) = result {
+        eprintln!("ERROR: Unable to write report, reason {}", e);
+        process::exit(EXIT_ERROR);
     }
+}
 
-    ignores
+/// Open the user supplied baseline file which contains the hash signatures for text that we
+/// don't want to report on.  Expired entries are left out.
+fn get_ignores(file_name: &str, opts: &Options) -> baseline::Matcher {
+    if !(0.0..=100.0).contains(&opts.baseline_threshold) {
+        eprintln!("ERROR: --baseline-threshold must be between 0 and 100");
+        process::exit(EXIT_ERROR);
+    }
+    match baseline::Baseline::load(file_name) {
+        Ok(b) => baseline::Matcher::new(
+            b,
+            &baseline::today(),
+            opts.baseline_match,
+            opts.baseline_threshold / 100.0,
+        ),
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(EXIT_ERROR);
+        }
+    }
 }
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
 struct LineId {
     file_id: u32,
     line_number: u32,
@@ -531,19 +778,29 @@ struct LineId {
 #[derive(Debug)]
 struct FileId {
     num_files: u32,
+    /// How many of the files are from a reference corpus rather than scanned.
+    corpus_files: u32,
     index_to_name: Vec<Arc<String>>,
     name_to_index: HashMap<Arc<String>, u32>,
+    root: Option<PathBuf>,
 }
 
 impl FileId {
     fn new() -> FileId {
         FileId {
             num_files: 0,
+            corpus_files: 0,
             index_to_name: vec![],
             name_to_index: HashMap::new(),
+            root: None,
         }
     }
 
+    /// Set the directory which file names are displayed relative to.
+    fn set_root(&mut self, root: PathBuf) {
+        self.root = Some(root);
+    }
+
     /// Given a file name, if it doesn't already exist we will store the information about which
     /// index it is stored in and it's value.
     fn register_file(&mut self, file_name: Arc<String>) -> Option<u32> {
@@ -555,13 +812,27 @@ impl FileId {
         self.index_to_name.push(Arc::clone(&file_name));
         self.name_to_index
             .insert(Arc::clone(&file_name), self.num_files);
+        self.next_file();
+        Some(num)
+    }
+
+    /// Add a file from a reference corpus, which doesn't stop a file of the same name being
+    /// registered as one of the files being scanned.
+    fn register_corpus_file(&mut self, file_name: Arc<String>) -> u32 {
+        let num = self.num_files;
+        self.index_to_name.push(file_name);
+        self.next_file();
+        self.corpus_files += 1;
+        num
+    }
+
+    fn next_file(&mut self) {
         if let Some(v) = self.num_files.checked_add(1) {
             self.num_files = v;
         } else {
             eprintln!("Number of files processed exceeds {}", u32::MAX);
-            process::exit(2);
+            process::exit(EXIT_ERROR);
         }
-        Some(num)
     }
 
     /// Given an id (integer) return the actual file name.
@@ -569,14 +840,99 @@ impl FileId {
         self.index_to_name[index as usize].clone()
     }
 
-    /// Number of files we have information for.
+    /// Given an id return the file name to show the user, which is relative to the root when
+    /// the file is within it.  Internally we always use the canonical name.
+    fn id_to_display_name(&self, index: u32) -> String {
+        let name = &self.index_to_name[index as usize];
+        if let Some(root) = &self.root {
+            if let Ok(rel) = Path::new(name.as_str()).strip_prefix(root) {
+                return rel.to_string_lossy().to_string();
+            }
+        }
+        name.to_string()
+    }
+
+    /// Number of files we have information for, which were scanned rather than from a corpus.
     fn number_files(&self) -> u32 {
-        self.num_files
+        self.num_files - self.corpus_files
+    }
+}
+
+/// The root used for displaying file names when the user didn't supply one, which is the top
+/// level of the git repository we are in, else the directory of the configuration file, else the
+/// current working directory.
+fn default_root(config_dir: Option<&Path>) -> PathBuf {
+    if let Ok(out) = process::Command::new("git")
+        .args(["rev-parse", "--show-toplevel"])
+        .stderr(process::Stdio::null())
+        .output()
+    {
+        if out.status.success() {
+            let top = String::from_utf8_lossy(&out.stdout).trim().to_string();
+            if !top.is_empty() {
+                return PathBuf::from(top);
+            }
+        }
+    }
+    if let Some(d) = config_dir {
+        return d.to_path_buf();
+    }
+    std::env::current_dir().unwrap_or_else(|_| PathBuf::from("."))
+}
+
+/// Resolve the root directory, exiting if the user supplied one we can't use.
+fn resolve_root(root: &str, config_dir: Option<&Path>) -> PathBuf {
+    if root.is_empty() {
+        let r = default_root(config_dir);
+        canonicalize(&r).unwrap_or(r)
+    } else {
+        match canonicalize(root) {
+            Ok(r) if r.is_dir() => r,
+            Ok(_) => {
+                eprintln!("ERROR: --root {} is not a directory", root);
+                process::exit(EXIT_ERROR);
+            }
+            Err(e) => {
+                eprintln!("ERROR: Unable to use --root {}, reason {}", root, e);
+                process::exit(EXIT_ERROR);
+            }
+        }
+    }
+}
+
+/// Is the file excluded by `--exclude` or the configuration file, or because it isn't in a
+/// submission with `--submissions`.
+fn excluded(opts: &Options, file_name: &Path) -> bool {
+    if let Some(dir) = &opts.submissions_dir {
+        if submission_of(dir, file_name).is_none() {
+            return true;
+        }
     }
+    opts.exclude_patterns
+        .iter()
+        .any(|p| p.matches_path(file_name))
+        || opts
+            .path_rules
+            .iter()
+            .rev()
+            .find(|r| r.settings.exclude.is_some() && r.pattern.matches_path(file_name))
+            .and_then(|r| r.settings.exclude)
+            .unwrap_or(false)
+}
+
+/// The minimum number of duplicate lines for a file, the last matching `[paths]` section of
+/// the configuration file which sets it wins.
+fn min_lines_for(opts: &Options, file_name: &str) -> u32 {
+    opts.path_rules
+        .iter()
+        .rev()
+        .find(|r| r.settings.lines.is_some() && r.pattern.matches(file_name))
+        .and_then(|r| r.settings.lines)
+        .unwrap_or(opts.lines)
 }
 
 /// Get all files matching `file_globs` and update the global `FILE_LOOKUP`
-fn files_to_process(file_globs: &[String]) -> Vec<(u32, Arc<String>)> {
+fn files_to_process(file_globs: &[String], opts: &Options) -> Vec<(u32, Arc<String>)> {
     let mut files_to_process = Vec::new();
     // Hold the lock on FILE_LOOKUP for the duration as we are single threaded here.
     let mut file_lookup_locked = FILE_LOOKUP.lock().unwrap();
@@ -586,7 +942,7 @@ fn files_to_process(file_globs: &[String]) -> Vec<(u32, Arc<String>)> {
             Ok(entries) => entries,
             Err(e) => {
                 eprintln!("Bad glob pattern supplied '{}', error: {}", g, e);
-                process::exit(1);
+                process::exit(EXIT_ERROR);
             }
         };
         for filename in entries {
@@ -594,7 +950,7 @@ fn files_to_process(file_globs: &[String]) -> Vec<(u32, Arc<String>)> {
                 Ok(specific_file) => specific_file,
                 Err(e) => {
                     eprintln!("Unable to process {:?}", e);
-                    process::exit(1);
+                    process::exit(EXIT_ERROR);
                 }
             };
             if !specific_file.is_file() {
@@ -603,6 +959,7 @@ fn files_to_process(file_globs: &[String]) -> Vec<(u32, Arc<String>)> {
             let file_str_name = specific_file.to_str().unwrap();
 
             match canonicalize(file_str_name) {
+                Ok(fn_ok) if excluded(opts, &fn_ok) => {}
                 Ok(fn_ok) => {
                     let c_name_str = fn_ok.to_str().unwrap();
                     let name = Arc::new(c_name_str.to_string());
@@ -624,49 +981,1090 @@ fn files_to_process(file_globs: &[String]) -> Vec<(u32, Arc<String>)> {
     files_to_process
 }
 
-/// Command line options.
-#[derive(Debug)]
-pub struct Options {
-    lines: u32,
-    print: bool,
-    json: bool,
-    file_globs: Vec<String>,
-    ignore: String,
-    threads: usize,
+/// Sets of files where only duplication between different sets is of interest, `--left` and
+/// `--right` or a set per submission directory with `--submissions`.
+#[derive(Debug, Default)]
+struct Groups {
+    names: Vec<String>,
+    /// The set each file is in, indexed by file id.
+    of_file: Vec<u32>,
 }
 
-/// Default values for the command line options.
-impl Default for Options {
-    fn default() -> Options {
-        Options {
-            lines: 6,
-            print: false,
-            json: false,
-            file_globs: vec![],
-            ignore: "".to_string(),
-            threads: 4,
+/// The submission a file is in, which is the directory directly inside the submissions
+/// directory.  Files directly in the submissions directory aren't in any.
+fn submission_of(dir: &Path, file_name: &Path) -> Option<String> {
+    let rel = file_name.strip_prefix(dir).ok()?;
+    let mut components = rel.components();
+    let first = components.next()?;
+    components.next()?;
+    Some(first.as_os_str().to_string_lossy().to_string())
+}
+
+/// The files to scan along with the set of each, the sets are empty unless `--left` and
+/// `--right` or `--submissions` were supplied.  A file matching both `--left` and `--right`
+/// is only in the left set.
+fn grouped_files(opts: &Options) -> (Vec<(u32, Arc<String>)>, Groups) {
+    if !opts.left.is_empty() {
+        let mut files = files_to_process(&opts.left, opts);
+        let mut of_file = vec![0; files.len()];
+        let right = files_to_process(&opts.right, opts);
+        of_file.resize(files.len() + right.len(), 1);
+        files.extend(right);
+        let names = vec!["left".to_string(), "right".to_string()];
+        return (files, Groups { names, of_file });
+    }
+
+    let files = files_to_process(&opts.file_globs, opts);
+    let dir = match &opts.submissions_dir {
+        Some(dir) => dir,
+        None => return (files, Groups::default()),
+    };
+
+    // Files outside of a submission were excluded, so every file has one.
+    let submissions: Vec<String> = files
+        .iter()
+        .map(|(_, f)| submission_of(dir, Path::new(f.as_str())).unwrap_or_default())
+        .collect();
+    let mut names = submissions.clone();
+    names.sort();
+    names.dedup();
+    let of_file = submissions
+        .iter()
+        .map(|s| names.binary_search(s).unwrap() as u32)
+        .collect();
+    (files, Groups { names, of_file })
+}
+
+/// Lines of each file covered by an instance of a duplicate, indexed by file id.  Only the
+/// instances for which `wanted` returns true are included.
+fn covered_lines<F: Fn(&report::Duplicate, u32) -> bool>(
+    r: &report::Report,
+    file_hashes: &[Vec<u64>],
+    ids: &HashMap<String, u32>,
+    wanted: F,
+) -> Vec<Vec<bool>> {
+    let mut covered: Vec<Vec<bool>> = file_hashes.iter().map(|f| vec![false; f.len()]).collect();
+    for d in &r.duplicates {
+        for i in &d.instances {
+            if let Some(&id) = ids.get(&i.path) {
+                if !wanted(d, id) {
+                    continue;
+                }
+                let lines = &mut covered[id as usize];
+                let end = (i.end_line as usize).min(lines.len());
+                for l in &mut lines[(i.start_line - 1) as usize..end] {
+                    *l = true;
+                }
+            }
         }
     }
+    covered
 }
 
-static LONG_DESC: &str = "Find duplicate lines of text in one or more text files.
+/// File ids by the name used in the report.
+fn display_name_ids(num_files: usize) -> HashMap<String, u32> {
+    let file_lookup_locked = FILE_LOOKUP.lock().unwrap();
+    (0..num_files as u32)
+        .map(|i| (file_lookup_locked.id_to_display_name(i), i))
+        .collect()
+}
 
-The duplicated text can be at different levels of indention,
-but otherwise needs to be identical.
+/// How much of each set is duplicated in the other one.
+fn group_coverage(
+    r: &report::Report,
+    groups: &Groups,
+    file_hashes: &[Vec<u64>],
+) -> Vec<report::GroupCoverage> {
+    let ids = display_name_ids(groups.of_file.len());
+    let covered = covered_lines(r, file_hashes, &ids, |_, _| true);
+
+    (0..groups.names.len() as u32)
+        .map(|g| {
+            let ids: Vec<usize> = (0..groups.of_file.len())
+                .filter(|&i| groups.of_file[i] == g)
+                .collect();
+            let dup: Vec<u64> = ids
+                .iter()
+                .map(|&i| covered[i].iter().filter(|&&c| c).count() as u64)
+                .collect();
+            report::GroupCoverage {
+                name: groups.names[g as usize].clone(),
+                files: ids.len() as u32,
+                lines: ids.iter().map(|&i| file_hashes[i].len() as u64).sum(),
+                files_duplicated: dup.iter().filter(|&&n| n > 0).count() as u32,
+                duplicate_lines: dup.iter().sum(),
+            }
+        })
+        .collect()
+}
 
-More information: https://github.com/tasleson/duplihere";
+/// Lines each pair of submissions share, most similar first.  Lines which are in `--base`,
+/// `base_lines` when there are any, aren't counted.
+fn submission_pairs(
+    r: &report::Report,
+    groups: &Groups,
+    file_hashes: &[Vec<u64>],
+    base_lines: &[Vec<bool>],
+) -> Vec<report::SubmissionPair> {
+    let ids = display_name_ids(groups.of_file.len());
+    let num = groups.names.len();
+    let group_of = |id: u32| groups.of_file[id as usize];
+
+    let mut lines = vec![0u64; num];
+    for (id, f) in file_hashes.iter().enumerate() {
+        let base = base_lines
+            .get(id)
+            .map_or(0, |b| b.iter().filter(|&&b| b).count());
+        lines[group_of(id as u32) as usize] += (f.len() - base) as u64;
+    }
 
-fn main() -> Result<(), rags::Error> {
-    let mut opts = Options::default();
-    let mut parser = argparse!();
-    parser
-        .app_desc("find duplicate text")
-        .app_long_desc(LONG_DESC)
-        .group("argument", "description")?
-        .flag('p', "print", "print duplicate text", &mut opts.print, false)?
-        .flag('j', "json", "output JSON", &mut opts.json, false)?
-        .arg(
-            'l',
+    // The submissions each duplicate has instances in.
+    let present: HashMap<u64, Vec<u32>> = r
+        .duplicates
+        .iter()
+        .map(|d| {
+            let mut g: Vec<u32> = d
+                .instances
+                .iter()
+                .filter_map(|i| ids.get(&i.path).map(|&id| group_of(id)))
+                .collect();
+            g.sort_unstable();
+            g.dedup();
+            (d.key, g)
+        })
+        .collect();
+
+    // shared[a][b] is the number of lines of submission a which are also in b.
+    let mut shared = vec![vec![0u64; num]; num];
+    for b in 0..num as u32 {
+        let covered = covered_lines(r, file_hashes, &ids, |d, id| {
+            group_of(id) != b && present[&d.key].contains(&b)
+        });
+        for (id, c) in covered.iter().enumerate() {
+            shared[group_of(id as u32) as usize][b as usize] +=
+                c.iter().filter(|&&c| c).count() as u64;
+        }
+    }
+
+    let mut rc = Vec::new();
+    for a in 0..num {
+        for b in a + 1..num {
+            if shared[a][b] > 0 || shared[b][a] > 0 {
+                rc.push(report::SubmissionPair {
+                    left: groups.names[a].clone(),
+                    right: groups.names[b].clone(),
+                    left_lines: lines[a],
+                    right_lines: lines[b],
+                    left_shared: shared[a][b],
+                    right_shared: shared[b][a],
+                });
+            }
+        }
+    }
+    rc.sort_by(|x, y| y.similarity().total_cmp(&x.similarity()));
+    rc
+}
+
+/// The directories, up to `depth` deep, a file is in, eg. `drivers` and `drivers/net` for
+/// `drivers/net/foo.c` with a depth of 2.  Files at the top are in `.`.
+fn file_directories(path: &str, depth: usize) -> Vec<String> {
+    let parent: Vec<String> = Path::new(path)
+        .parent()
+        .map(|p| {
+            p.components()
+                .map(|c| c.as_os_str().to_string_lossy().to_string())
+                .collect()
+        })
+        .unwrap_or_default();
+    if parent.is_empty() {
+        return vec![".".to_string()];
+    }
+    (1..=parent.len().min(depth))
+        .map(|n| {
+            parent[..n]
+                .iter()
+                .collect::<PathBuf>()
+                .to_string_lossy()
+                .to_string()
+        })
+        .collect()
+}
+
+/// Duplication in each directory up to `depth` deep, the most duplicated lines first.
+fn directory_summary(
+    r: &report::Report,
+    file_hashes: &[Vec<u64>],
+    depth: usize,
+) -> Vec<report::DirectorySummary> {
+    let ids = display_name_ids(file_hashes.len());
+    let covered = covered_lines(r, file_hashes, &ids, |_, _| true);
+    let mut dirs: HashMap<String, report::DirectorySummary> = HashMap::new();
+
+    for (name, &id) in &ids {
+        let lines = file_hashes[id as usize].len() as u64;
+        let dup = covered[id as usize].iter().filter(|&&c| c).count() as u64;
+        for d in file_directories(name, depth) {
+            let e = dirs
+                .entry(d.clone())
+                .or_insert_with(|| report::DirectorySummary::new(d));
+            e.files += 1;
+            e.lines += lines;
+            e.duplicate_lines += dup;
+        }
+    }
+
+    for dup in &r.duplicates {
+        let in_dirs: Vec<Vec<String>> = dup
+            .instances
+            .iter()
+            .map(|i| file_directories(&i.path, depth))
+            .collect();
+        let mut all: Vec<&String> = in_dirs.iter().flatten().collect();
+        all.sort();
+        all.dedup();
+        for d in all {
+            if let Some(e) = dirs.get_mut(d) {
+                if in_dirs.iter().all(|i| i.contains(d)) {
+                    e.clones_within += 1;
+                } else {
+                    e.clones_shared += 1;
+                }
+            }
+        }
+    }
+
+    let mut rc: Vec<report::DirectorySummary> = dirs.into_values().collect();
+    rc.sort_by(|a, b| {
+        b.duplicate_lines
+            .cmp(&a.duplicate_lines)
+            .then_with(|| a.path.cmp(&b.path))
+    });
+    rc
+}
+
+/// Rolling hashes of the files matching `--base`, eg. starter code every submission has.
+fn base_hashes(opts: &Options, min_lines: u32) -> HashSet<u64> {
+    let mut files = Vec::new();
+    for g in &opts.base {
+        match glob(g) {
+            Ok(entries) => files.extend(entries.flatten().filter(|f| f.is_file())),
+            Err(e) => {
+                eprintln!("Bad glob pattern supplied '{}', error: {}", g, e);
+                process::exit(EXIT_ERROR);
+            }
+        }
+    }
+
+    files
+        .par_iter()
+        .flat_map_iter(|f| {
+            let (signatures, _) = file_signatures(&f.to_string_lossy());
+            rolling_hashes(&signatures, min_lines as usize)
+                .into_iter()
+                .map(|(h, _)| h)
+        })
+        .collect()
+}
+
+/// Take the text found in `--base` out of the scan.  The lines of the windows whose hash is in
+/// `base` are given signatures which match nothing, so duplicates can't be extended over them,
+/// and the windows are dropped.  Returns the lines of each file this was done to.
+fn subtract_base(
+    collision_hashes: &DashMap<u64, Vec<LineId>>,
+    file_hashes: &mut [Vec<u64>],
+    base: &HashSet<u64>,
+    min_lines: u32,
+) -> Vec<Vec<bool>> {
+    let mut covered: Vec<Vec<bool>> = file_hashes.iter().map(|f| vec![false; f.len()]).collect();
+    collision_hashes.retain(|k, v| {
+        if !base.contains(k) {
+            return true;
+        }
+        for l in v.iter() {
+            let lines = &mut covered[l.file_id as usize];
+            let end = ((l.line_number + min_lines) as usize).min(lines.len());
+            lines[l.line_number as usize..end].fill(true);
+        }
+        false
+    });
+
+    for (id, (f, c)) in file_hashes.iter_mut().zip(&covered).enumerate() {
+        for (n, (h, &c)) in f.iter_mut().zip(c).enumerate() {
+            if c {
+                *h = calculate_hash((id, n, "--base"));
+            }
+        }
+    }
+    covered
+}
+
+/// The signature cache, when `--cache` was supplied.
+fn load_cache(opts: &Options, min_lines: u32) -> Option<cache::Cache> {
+    if opts.cache.is_empty() {
+        None
+    } else {
+        Some(cache::Cache::load(&opts.cache, min_lines, opts.normalize))
+    }
+}
+
+/// Write the signature cache, failing to is only worth a warning as the results are fine.
+fn save_cache(cache: Option<cache::Cache>, opts: &Options, files: &[(u32, Arc<String>)]) {
+    if let Some(c) = cache {
+        if let Err(e) = c.save(&opts.cache, files) {
+            eprintln!("WARNING: {}", e);
+        }
+    }
+}
+
+/// Find all the duplicates in the files matching the user supplied globs, only keeping those
+/// which touch the changed lines when there are any.
+fn scan(
+    opts: &Options,
+    ignores: &baseline::Matcher,
+    changes: Option<&diff::ChangedLines>,
+) -> report::Report {
+    let (files_to_process, groups) = grouped_files(opts);
+    let file_changes: Option<Vec<Vec<(u32, u32)>>> = changes.map(|c| {
+        files_to_process
+            .iter()
+            .map(|(_, name)| c.get(name.as_str()).cloned().unwrap_or_default())
+            .collect()
+    });
+
+    // Scan with the smallest number of lines anything wants, the larger per file minimums are
+    // applied to the results.
+    let file_min_lines: Vec<u32> = files_to_process
+        .iter()
+        .map(|(_, name)| min_lines_for(opts, name))
+        .collect();
+    let min_lines = file_min_lines.iter().copied().min().unwrap_or(opts.lines);
+
+    let mut hashing = Hashing::new(opts, files_to_process.len(), min_lines);
+    hashing.process_files(&files_to_process, opts);
+    let (mut files, collision_hashes) = hashing.finish();
+
+    files.stats.scanned_lines = files.file_hashes.iter().map(|f| f.len() as u64).sum();
+    let base_lines = if opts.base.is_empty() {
+        vec![]
+    } else {
+        let base = base_hashes(opts, min_lines);
+        subtract_base(&collision_hashes, &mut files.file_hashes, &base, min_lines)
+    };
+    let results_hash = find_collisions(
+        collision_hashes,
+        &mut files.file_hashes,
+        &groups.of_file,
+        min_lines,
+    );
+
+    files.file_min_lines = file_min_lines;
+    files.file_changes = file_changes;
+    let mut r = process_report(results_hash, &files, opts, ignores);
+    let file_hashes = files.file_hashes;
+    if opts.submissions_dir.is_some() {
+        r.submissions = groups.names.clone();
+        r.pairs = submission_pairs(&r, &groups, &file_hashes, &base_lines);
+    } else if !groups.names.is_empty() {
+        r.groups = group_coverage(&r, &groups, &file_hashes);
+    }
+    if let Some(depth) = opts.depth.0 {
+        r.directories = directory_summary(&r, &file_hashes, depth);
+    }
+    r
+}
+
+/// `search` looks for the text of a snippet, from a file or stdin, in the files matching the
+/// globs.  The snippet is treated as one more file, but only its rolling hashes are registered
+/// so that just the duplicates which include it are found.  The longest matches come first and
+/// the snippet is the last instance of each.
+fn search(opts: &Options) -> report::Report {
+    let (name, text) = if opts.snippet.is_empty() || opts.snippet == "-" {
+        let mut text = Vec::new();
+        if let Err(e) = std::io::stdin().read_to_end(&mut text) {
+            eprintln!("ERROR: Unable to read snippet from stdin, reason {}", e);
+            process::exit(EXIT_ERROR);
+        }
+        (report::STDIN_NAME.to_string(), text)
+    } else {
+        let name = canonicalize(&opts.snippet)
+            .map(|p| p.to_string_lossy().to_string())
+            .unwrap_or_else(|_| opts.snippet.clone());
+        match std::fs::read(&opts.snippet) {
+            Ok(text) => (name, text),
+            Err(e) => {
+                eprintln!("ERROR: Unable to read {}, reason {}", opts.snippet, e);
+                process::exit(EXIT_ERROR);
+            }
+        }
+    };
+
+    // Registered first so that when the snippet is a file matching the globs it isn't also
+    // scanned as part of the tree.
+    let snippet_id = FILE_LOOKUP
+        .lock()
+        .unwrap()
+        .register_file(Arc::new(name.clone()))
+        .unwrap();
+    let files_to_process: Vec<(u32, Arc<String>)> = files_to_process(&opts.file_globs, opts);
+    let num_files = files_to_process.len() + 1;
+
+    let (snippet_signatures, snippet_suppressed) = signatures(&text[..], &name);
+    let collision_hashes: DashMap<u64, Vec<LineId>> = DashMap::new();
+    for (r_hash, line_number) in rolling_hashes(&snippet_signatures, opts.lines as usize) {
+        collision_hashes.entry(r_hash).or_default().push(LineId {
+            file_id: snippet_id,
+            line_number,
+        });
+    }
+
+    let mut file_hashes = vec![vec![]; num_files];
+    let mut file_suppressions = vec![vec![]; num_files];
+    file_hashes[snippet_id as usize] = snippet_signatures;
+    file_suppressions[snippet_id as usize] = snippet_suppressed;
+
+    let mut hashing = Hashing::known(
+        opts,
+        file_hashes,
+        file_suppressions,
+        collision_hashes,
+        opts.lines,
+    );
+    hashing.process_files(&files_to_process, opts);
+    let (mut files, collision_hashes) = hashing.finish();
+
+    // Text from stdin isn't a file, so it's left out of the statistics.
+    let from_stdin = name == report::STDIN_NAME;
+    files.stats.scanned_lines = files
+        .file_hashes
+        .iter()
+        .enumerate()
+        .filter(|(n, _)| !from_stdin || *n != snippet_id as usize)
+        .map(|(_, f)| f.len() as u64)
+        .sum();
+    let results_hash = find_collisions(collision_hashes, &mut files.file_hashes, &[], opts.lines);
+    results_hash.retain(|_, c| c.start_lines.iter().any(|l| l.file_id == snippet_id));
+
+    files.file_min_lines = vec![opts.lines; num_files];
+    let mut r = process_report(results_hash, &files, opts, &baseline::Matcher::empty());
+
+    let snippet_name = FILE_LOOKUP.lock().unwrap().id_to_display_name(snippet_id);
+    r.duplicates.reverse();
+    for d in &mut r.duplicates {
+        d.instances.sort_by_key(|i| i.path == snippet_name);
+    }
+
+    if from_stdin {
+        r.summary.files -= 1;
+        r.file_lines.retain(|(f, _)| f != report::STDIN_NAME);
+        r.stdin = Some(text);
+    }
+    r
+}
+
+/// `index build` hashes the files matching the globs and writes everything needed to look for
+/// copies of them to an index file.
+fn index_build(opts: &Options) {
+    let files_to_process: Vec<(u32, Arc<String>)> = files_to_process(&opts.file_globs, opts);
+    let mut hashing = Hashing::new(opts, files_to_process.len(), opts.lines);
+    hashing.process_files(&files_to_process, opts);
+    let (files, collision_hashes) = hashing.finish();
+
+    let mut index = index::Index::new(opts.lines, opts.normalize);
+    index.files = files_to_process
+        .iter()
+        .map(|(_, n)| n.to_string())
+        .collect();
+    index.signatures = files.file_hashes;
+    index.suppressed = files.file_suppressions;
+    index.table = collision_hashes.into_iter().collect();
+
+    if let Err(e) = index.save(&opts.index_file) {
+        eprintln!("ERROR: {}", e);
+        process::exit(EXIT_ERROR);
+    }
+    println!(
+        "Index {}: {} files, {} lines",
+        opts.index_file,
+        index.files.len(),
+        index.lines_indexed()
+    );
+}
+
+/// `scan --against` looks for copies of the corpus in an index in the files matching the globs.
+/// Only the duplicates with instances in both the corpus and the scanned files are reported.
+/// The corpus files come first in `FILE_LOOKUP`, so any id from the scanned files is at least
+/// the number of corpus files.
+fn scan_against(
+    opts: &mut Options,
+    ignores: &baseline::Matcher,
+    changes: Option<&diff::ChangedLines>,
+) -> report::Report {
+    let index = match index::Index::load(&opts.against) {
+        Ok(i) => i,
+        Err(e) => {
+            eprintln!("ERROR: {}", e);
+            process::exit(EXIT_ERROR);
+        }
+    };
+
+    // The rolling hashes in the index dictate these, so asking for anything else is an error.
+    if opts.lines_given && opts.lines != index.lines {
+        eprintln!(
+            "ERROR: -l {} differs from the {} lines index {} was built with",
+            opts.lines, index.lines, opts.against
+        );
+        process::exit(EXIT_ERROR);
+    }
+    if opts.normalize_given && opts.normalize != index.normalize {
+        eprintln!(
+            "ERROR: --normalize {} differs from the {} index {} was built with",
+            opts.normalize, index.normalize, opts.against
+        );
+        process::exit(EXIT_ERROR);
+    }
+    opts.lines = index.lines;
+    opts.normalize = index.normalize;
+    COLLAPSE_WHITESPACE.store(opts.normalize == Normalize::Whitespace, Ordering::Relaxed);
+
+    {
+        let mut file_lookup_locked = FILE_LOOKUP.lock().unwrap();
+        for f in index.files {
+            file_lookup_locked.register_corpus_file(Arc::new(f));
+        }
+    }
+    let corpus_files = index.signatures.len();
+    let first_new = corpus_files as u32;
+
+    let files_to_process: Vec<(u32, Arc<String>)> = files_to_process(&opts.file_globs, opts);
+    let num_files = corpus_files + files_to_process.len();
+
+    // The configuration file can raise the minimum for the scanned files, but the index has no
+    // hashes for fewer lines than it was built with.
+    let mut file_min_lines = vec![index.lines; corpus_files];
+    for (_, name) in &files_to_process {
+        let lines = min_lines_for(opts, name);
+        if lines < index.lines {
+            eprintln!(
+                "ERROR: {} has a minimum of {} lines, less than the {} lines index {} was \
+                built with",
+                name, lines, index.lines, opts.against
+            );
+            process::exit(EXIT_ERROR);
+        }
+        file_min_lines.push(lines);
+    }
+
+    let mut signatures = index.signatures;
+    signatures.resize(num_files, vec![]);
+    let mut suppressed = index.suppressed;
+    suppressed.resize(num_files, vec![]);
+    let mut hashing = Hashing::known(
+        opts,
+        signatures,
+        suppressed,
+        index.table.into_iter().collect(),
+        opts.lines,
+    );
+    hashing.process_files(&files_to_process, opts);
+    let (mut files, collision_hashes) = hashing.finish();
+
+    // Only the corpus hashes which the scanned files share are of interest.
+    collision_hashes.retain(|_, v| v.iter().any(|l| l.file_id >= first_new));
+
+    files.stats.scanned_lines = files.file_hashes[corpus_files..]
+        .iter()
+        .map(|f| f.len() as u64)
+        .sum();
+    let results_hash = find_collisions(collision_hashes, &mut files.file_hashes, &[], opts.lines);
+    results_hash.retain(|_, c| {
+        c.start_lines.iter().any(|l| l.file_id < first_new)
+            && c.start_lines.iter().any(|l| l.file_id >= first_new)
+    });
+
+    let file_changes: Option<Vec<Vec<(u32, u32)>>> = changes.map(|c| {
+        let file_lookup_locked = FILE_LOOKUP.lock().unwrap();
+        (0..num_files as u32)
+            .map(|i| {
+                c.get(file_lookup_locked.id_to_name(i).as_str())
+                    .cloned()
+                    .unwrap_or_default()
+            })
+            .collect()
+    });
+
+    files.file_min_lines = file_min_lines;
+    files.file_changes = file_changes;
+    process_report(results_hash, &files, opts, ignores)
+}
+
+/// The lines changed by `--diff` and `--git-diff`, if either was supplied.  Paths in a patch
+/// file are relative to the root or the current working directory.
+fn changed_lines(opts: &Options, root: &Path) -> Option<diff::ChangedLines> {
+    if opts.diff.is_empty() && opts.git_diff.is_empty() {
+        return None;
+    }
+
+    let mut rc = diff::ChangedLines::new();
+    let mut add = |text: &str, bases: &[PathBuf]| {
+        for (f, ranges) in diff::parse(text, bases) {
+            rc.entry(f).or_default().extend(ranges);
+        }
+    };
+
+    if !opts.diff.is_empty() {
+        let cwd = std::env::current_dir().unwrap_or_else(|_| PathBuf::from("."));
+        match diff::read_patch(&opts.diff) {
+            Ok(text) => add(&text, &[root.to_path_buf(), cwd]),
+            Err(e) => {
+                eprintln!("ERROR: {}", e);
+                process::exit(EXIT_ERROR);
+            }
+        }
+    }
+    if !opts.git_diff.is_empty() {
+        match diff::git_changes(&opts.git_diff) {
+            Ok(changes) => {
+                for (f, ranges) in changes {
+                    rc.entry(f).or_default().extend(ranges);
+                }
+            }
+            Err(e) => {
+                eprintln!("ERROR: --git-diff {}", e);
+                process::exit(EXIT_ERROR);
+            }
+        }
+    }
+    Some(rc)
+}
+
+/// `baseline create` writes every duplicate in the report to a new baseline file,
+/// `baseline update` adds new duplicates to an existing one and prunes the entries which no
+/// longer match anything.
+fn baseline_command(r: &report::Report, opts: &Options) {
+    let update = opts.subcmds.iter().any(|s| s == "update");
+    let mut b = if update {
+        match baseline::Baseline::load(&opts.baseline_file) {
+            Ok(b) => b,
+            Err(e) => {
+                eprintln!("{}", e);
+                process::exit(EXIT_ERROR);
+            }
+        }
+    } else {
+        baseline::Baseline::new()
+    };
+
+    let reason = Some(opts.reason.clone()).filter(|s| !s.is_empty());
+    let author = Some(opts.author.clone()).filter(|s| !s.is_empty());
+    let changes = b.update(
+        &r.duplicates,
+        opts.baseline_match,
+        opts.baseline_threshold / 100.0,
+        reason,
+        author,
+    );
+
+    if let Err(e) = b.save(&opts.baseline_file) {
+        eprintln!("{}", e);
+        process::exit(EXIT_ERROR);
+    }
+
+    println!(
+        "Baseline {}: {} added, {} removed, {} updated, {} unchanged",
+        opts.baseline_file, changes.added, changes.removed, changes.updated, changes.unchanged
+    );
+}
+
+/// An optional value on the command line, empty when not supplied.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Limit<T>(Option<T>);
+
+impl<T: FromStr> FromStr for Limit<T> {
+    type Err = T::Err;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(Limit(Some(s.parse()?)))
+    }
+}
+
+impl<T: fmt::Display> fmt::Display for Limit<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.0 {
+            Some(v) => write!(f, "{}", v),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Was one of the options `names` given on the command line, eg. `-l 6`, `-l6`, `--lines 6`
+/// or `--lines=6`.
+fn on_command_line(args: &[String], names: &[&str]) -> bool {
+    args.iter().skip(1).take_while(|a| *a != "--").any(|a| {
+        names.iter().any(|n| {
+            if n.starts_with("--") {
+                a == n || a.strip_prefix(n).is_some_and(|r| r.starts_with('='))
+            } else {
+                a.starts_with(n) && !a.starts_with("--")
+            }
+        })
+    })
+}
+
+/// Exit with `EXIT_GATE` when any of the quality gates failed.
+fn check_gates(r: &report::Report, opts: &Options) {
+    let failures = gate_failures(r, opts);
+    if !failures.is_empty() {
+        for f in failures {
+            eprintln!("FAILED: {}", f);
+        }
+        process::exit(EXIT_GATE);
+    }
+}
+
+/// The `report` subcommand, filter and write a saved JSON report.  With `--print` the text is
+/// read from the files for the instances the report doesn't have it for.
+fn rewrite_report(opts: &Options, filters: &report::Filters) {
+    let mut r = match report::Report::load(&opts.report_file) {
+        Ok(r) => r,
+        Err(e) => {
+            eprintln!("ERROR: {}", e);
+            process::exit(EXIT_ERROR);
+        }
+    };
+    // The paths in the report are relative to the root of the scan, which `--root` replaces
+    // when the files have moved, and the text is checked the way the scan normalized it.
+    if !opts.root.is_empty() {
+        r.root = resolve_root(&opts.root, None).to_string_lossy().to_string();
+    } else if r.root.is_empty() {
+        r.root = r.options.root.clone().unwrap_or_default();
+    }
+    let normalize: Normalize = r.options.normalize.parse().unwrap_or(Normalize::Trim);
+    COLLAPSE_WHITESPACE.store(normalize == Normalize::Whitespace, Ordering::Relaxed);
+
+    r.apply_filters(filters);
+    write_report(&mut r, opts);
+    check_gates(&r, opts);
+}
+
+/// The `compare` subcommand, write what changed between two saved JSON reports to stdout.
+/// Filters apply to both reports, but not the sort order or `--top`.
+fn compare_reports(opts: &Options) {
+    let rc = filters(opts).and_then(|f| {
+        let f = report::Filters {
+            sort: None,
+            top: None,
+            ..f
+        };
+        let format = if !opts.format.is_empty() {
+            opts.format
+                .parse::<compare::Format>()
+                .map_err(|e| format!("--format {}", e))?
+        } else if opts.json {
+            compare::Format::Json
+        } else {
+            compare::Format::Text
+        };
+
+        let mut old = report::Report::load(&opts.old_report)?;
+        let mut new = report::Report::load(&opts.new_report)?;
+        old.apply_filters(&f);
+        new.apply_filters(&f);
+
+        let stdout = std::io::stdout();
+        let mut lock = stdout.lock();
+        compare::Comparison::new(&old, &new)
+            .write(format, &mut lock)
+            .map_err(|e| format!("Unable to write comparison, reason {}", e))
+    });
+
+    if let Err(e) = rc {
+        eprintln!("ERROR: {}", e);
+        process::exit(EXIT_ERROR);
+    }
+}
+
+/// The filters for the report from the command line.
+fn filters(opts: &Options) -> Result<report::Filters, String> {
+    if opts.cross_file_only && opts.same_file_only {
+        return Err("--cross-file-only and --same-file-only can't be used together".to_string());
+    }
+    let paths = opts
+        .path_filters
+        .iter()
+        .map(|p| {
+            glob::Pattern::new(p)
+                .map_err(|e| format!("Bad --path-filter pattern '{}', error: {}", p, e))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(report::Filters {
+        sort: opts.sort.0,
+        top: opts.top.0,
+        min_instances: opts.min_instances.0,
+        cross_file_only: opts.cross_file_only,
+        same_file_only: opts.same_file_only,
+        paths,
+    })
+}
+
+/// Check the report against the quality gates the user asked for, returning an explanation
+/// for each one which failed.
+fn gate_failures(r: &report::Report, opts: &Options) -> Vec<String> {
+    let mut rc = Vec::new();
+    let s = &r.summary;
+
+    if let Some(max) = opts.max_duplicate_lines.0 {
+        if s.duplicate_lines > max {
+            rc.push(format!(
+                "{} duplicate lines exceeds --max-duplicate-lines {}",
+                s.duplicate_lines, max
+            ));
+        }
+    }
+    if let Some(max) = opts.max_duplication_percent.0 {
+        let percent = r.duplication_percent();
+        if percent > max {
+            rc.push(format!(
+                "{:.2}% of {} scanned lines are duplicates, exceeds \
+                --max-duplication-percent {}",
+                percent, s.scanned_lines, max
+            ));
+        }
+    }
+    if let Some(max) = opts.max_clones.0 {
+        if s.clones > max {
+            rc.push(format!(
+                "{} duplicates exceeds --max-clones {}",
+                s.clones, max
+            ));
+        }
+    }
+    if opts.fail_on_new {
+        // Without a baseline everything is new.
+        let new = r
+            .duplicates
+            .iter()
+            .filter(|d| {
+                d.baseline_status.is_none()
+                    || d.baseline_status == Some(baseline::BaselineStatus::New)
+            })
+            .count();
+        if new > 0 {
+            rc.push(format!(
+                "{} new duplicates not in the baseline, --fail-on-new",
+                new
+            ));
+        }
+    }
+    if opts.fail_on_stale_ignores && !r.stale_ignores.is_empty() {
+        rc.push(format!(
+            "{} ignore entries in {} did not match any duplicate, --fail-on-stale-ignores",
+            r.stale_ignores.len(),
+            opts.ignore
+        ));
+    }
+    rc
+}
+
+/// Command line options.
+#[derive(Debug)]
+pub struct Options {
+    lines: u32,
+    print: bool,
+    json: bool,
+    file_globs: Vec<String>,
+    ignore: String,
+    threads: usize,
+    json_schema: bool,
+    outputs: Vec<output::OutputSpec>,
+    format: String,
+    root: String,
+    print_instances: snippet::PrintInstances,
+    context: usize,
+    color: snippet::ColorChoice,
+    subcmds: Vec<String>,
+    show_keys: Vec<u64>,
+    width: usize,
+    baseline_file: String,
+    reason: String,
+    author: String,
+    fail_on_stale_ignores: bool,
+    no_print: bool,
+    no_fail_on_new: bool,
+    no_fail_on_stale_ignores: bool,
+    baseline_match: baseline::MatchMode,
+    baseline_threshold: f64,
+    config: String,
+    no_config: bool,
+    excludes: Vec<String>,
+    exclude_patterns: Vec<glob::Pattern>,
+    path_rules: Vec<config::PathRule>,
+    normalize: Normalize,
+    max_duplicate_lines: Limit<u64>,
+    max_duplication_percent: Limit<f64>,
+    max_clones: Limit<u64>,
+    fail_on_new: bool,
+    diff: String,
+    git_diff: String,
+    snippet: String,
+    cache: String,
+    index_file: String,
+    against: String,
+    left: Vec<String>,
+    right: Vec<String>,
+    base: Vec<String>,
+    submissions: String,
+    submissions_dir: Option<PathBuf>,
+    depth: Limit<usize>,
+    sort: Limit<report::SortKey>,
+    top: Limit<usize>,
+    min_instances: Limit<usize>,
+    cross_file_only: bool,
+    same_file_only: bool,
+    path_filters: Vec<String>,
+    report_file: String,
+    /// `-l` and `--normalize` were given on the command line, rather than being defaults.
+    lines_given: bool,
+    normalize_given: bool,
+    old_report: String,
+    new_report: String,
+}
+
+/// Default values for the command line options.
+impl Default for Options {
+    fn default() -> Options {
+        Options {
+            lines: 6,
+            print: false,
+            json: false,
+            file_globs: vec![],
+            ignore: "".to_string(),
+            threads: 4,
+            json_schema: false,
+            outputs: vec![],
+            format: "".to_string(),
+            root: "".to_string(),
+            print_instances: snippet::PrintInstances::First,
+            context: 0,
+            color: snippet::ColorChoice::Auto,
+            subcmds: vec![],
+            show_keys: vec![],
+            width: 160,
+            baseline_file: "".to_string(),
+            reason: "".to_string(),
+            author: "".to_string(),
+            fail_on_stale_ignores: false,
+            no_print: false,
+            no_fail_on_new: false,
+            no_fail_on_stale_ignores: false,
+            baseline_match: baseline::MatchMode::Exact,
+            baseline_threshold: 50.0,
+            config: "".to_string(),
+            no_config: false,
+            excludes: vec![],
+            exclude_patterns: vec![],
+            path_rules: vec![],
+            normalize: Normalize::Trim,
+            max_duplicate_lines: Limit(None),
+            max_duplication_percent: Limit(None),
+            max_clones: Limit(None),
+            fail_on_new: false,
+            diff: "".to_string(),
+            git_diff: "".to_string(),
+            snippet: "".to_string(),
+            cache: "".to_string(),
+            index_file: "".to_string(),
+            against: "".to_string(),
+            left: vec![],
+            right: vec![],
+            base: vec![],
+            submissions: "".to_string(),
+            submissions_dir: None,
+            depth: Limit(None),
+            sort: Limit(None),
+            top: Limit(None),
+            min_instances: Limit(None),
+            cross_file_only: false,
+            same_file_only: false,
+            path_filters: vec![],
+            report_file: "".to_string(),
+            lines_given: false,
+            normalize_given: false,
+            old_report: "".to_string(),
+            new_report: "".to_string(),
+        }
+    }
+}
+
+static LONG_DESC: &str = "Find duplicate lines of text in one or more text files.
+
+The duplicated text can be at different levels of indention,
+but otherwise needs to be identical.
+
+Exit status is 0 when no quality gate failed, 1 when one did and 2 on error.
+
+More information: https://github.com/tasleson/duplihere";
+
+/// Define the command line, any values supplied are stored in `opts`.
+fn parse_args(parser: &mut rags::Parser, opts: &mut Options) -> Result<(), rags::Error> {
+    parser
+        .app_desc("find duplicate text")
+        .app_long_desc(LONG_DESC)
+        .group("argument", "description")?
+        .flag('p', "print", "print duplicate text", &mut opts.print, false)?
+        .long_flag(
+            "no-print",
+            "don't print duplicate text, overrides the configuration file",
+            &mut opts.no_print,
+            false,
+        )?
+        .long_arg(
+            "print-instances",
+            "which instances to print, one of first, all, differing (from the first)",
+            &mut opts.print_instances,
+            Some("<which>"),
+            false,
+        )?
+        .arg(
+            'C',
+            "context",
+            "number of lines of context to print around duplicate text",
+            &mut opts.context,
+            Some("<number>"),
+            false,
+        )?
+        .long_arg(
+            "color",
+            "color printed text, one of auto, always, never",
+            &mut opts.color,
+            Some("<when>"),
+            false,
+        )?
+        .flag('j', "json", "output JSON", &mut opts.json, false)?
+        .long_flag(
+            "json-schema",
+            "print the JSON Schema for the JSON output and exit",
+            &mut opts.json_schema,
+            false,
+        )?
+        .long_arg(
+            "format",
+            "format of the report written to stdout, same formats as --output",
+            &mut opts.format,
+            Some("<format>"),
+            false,
+        )?
+        .list(
+            'o',
+            "output",
+            "write a report, <format> is one of text, json, sarif, html, quickfix, lcov, dot, \
+            graphml and <path> defaults to stdout, can repeat",
+            &mut opts.outputs,
+            Some("<format>[=<path>]"),
+            false,
+        )?
+        .arg(
+            'l',
             "lines",
             "minimum number of duplicate lines",
             &mut opts.lines,
@@ -680,16 +2078,218 @@ fn main() -> Result<(), rags::Error> {
             \"file.ext\", can repeat",
             &mut opts.file_globs,
             Some("<pattern or specific file>"),
-            true,
+            false,
+        )?
+        .long_list(
+            "left",
+            "pattern or file of one set of files, with --right only duplicates between the \
+            two sets are reported, can repeat",
+            &mut opts.left,
+            Some("<pattern>"),
+            false,
+        )?
+        .long_list(
+            "right",
+            "pattern or file of the other set of files, can repeat",
+            &mut opts.right,
+            Some("<pattern>"),
+            false,
+        )?
+        .long_list(
+            "base",
+            "pattern or file of code everything starts from, eg. starter code, text in it \
+            isn't reported, can repeat",
+            &mut opts.base,
+            Some("<pattern>"),
+            false,
+        )?
+        .long_arg(
+            "submissions",
+            "each directory in this one is a submission, only duplicates between \
+            submissions are reported along with how similar each pair is",
+            &mut opts.submissions,
+            Some("<dir>"),
+            false,
+        )?
+        .list(
+            'x',
+            "exclude",
+            "pattern of files to skip, can repeat",
+            &mut opts.excludes,
+            Some("<pattern>"),
+            false,
+        )?
+        .long_arg(
+            "normalize",
+            "how lines are compared, trim ignores leading and trailing white space, \
+            whitespace also treats runs of white space within lines as one space",
+            &mut opts.normalize,
+            Some("<trim|whitespace>"),
+            false,
+        )?
+        .long_arg(
+            "config",
+            "configuration file, defaults to the first duplihere.toml found in the current \
+            working directory or its parents",
+            &mut opts.config,
+            Some("<file>"),
+            false,
+        )?
+        .long_flag(
+            "no-config",
+            "don't use a configuration file",
+            &mut opts.no_config,
+            false,
         )?
         .arg(
             'i',
             "ignore",
-            "file containing hash values to ignore, one per line",
+            "baseline file of duplicates to ignore, JSON or hash values one per line",
             &mut opts.ignore,
             Some("<file name>"),
             false,
         )?
+        .long_arg(
+            "baseline-match",
+            "how duplicates are matched to the ignore file entries, exact uses the hash \
+            signature, fuzzy also matches entries which overlap enough by location or text",
+            &mut opts.baseline_match,
+            Some("<exact|fuzzy>"),
+            false,
+        )?
+        .long_arg(
+            "baseline-threshold",
+            "percentage of an ignore entry a duplicate has to overlap for a fuzzy match",
+            &mut opts.baseline_threshold,
+            Some("<percent>"),
+            false,
+        )?
+        .long_arg(
+            "depth",
+            "summarize duplication for each directory up to this many levels deep, graphs \
+            show these directories instead of files",
+            &mut opts.depth,
+            Some("<number>"),
+            false,
+        )?
+        .long_arg(
+            "sort",
+            "order of the duplicates, most lines, most instances or highest score (lines \
+            times extra instances) first, or by path",
+            &mut opts.sort,
+            Some("<lines|instances|score|path>"),
+            false,
+        )?
+        .long_arg(
+            "top",
+            "only report this many duplicates, the highest scoring unless --sort is given",
+            &mut opts.top,
+            Some("<number>"),
+            false,
+        )?
+        .long_arg(
+            "min-instances",
+            "only report duplicates with at least this many instances",
+            &mut opts.min_instances,
+            Some("<number>"),
+            false,
+        )?
+        .long_flag(
+            "cross-file-only",
+            "only report duplicates with instances in more than one file",
+            &mut opts.cross_file_only,
+            false,
+        )?
+        .long_flag(
+            "same-file-only",
+            "only report duplicates with all their instances in one file",
+            &mut opts.same_file_only,
+            false,
+        )?
+        .long_list(
+            "path-filter",
+            "only report duplicates with an instance whose path, as shown, matches this \
+            pattern, can repeat",
+            &mut opts.path_filters,
+            Some("<pattern>"),
+            false,
+        )?
+        .long_arg(
+            "max-duplicate-lines",
+            "fail when more lines than this are duplicates",
+            &mut opts.max_duplicate_lines,
+            Some("<number>"),
+            false,
+        )?
+        .long_arg(
+            "max-duplication-percent",
+            "fail when more than this percentage of the scanned lines are duplicates",
+            &mut opts.max_duplication_percent,
+            Some("<percent>"),
+            false,
+        )?
+        .long_arg(
+            "max-clones",
+            "fail when more than this number of duplicates are found",
+            &mut opts.max_clones,
+            Some("<number>"),
+            false,
+        )?
+        .long_flag(
+            "fail-on-new",
+            "fail when there are duplicates which aren't in the ignore file",
+            &mut opts.fail_on_new,
+            false,
+        )?
+        .long_flag(
+            "no-fail-on-new",
+            "don't fail on new duplicates, overrides the configuration file",
+            &mut opts.no_fail_on_new,
+            false,
+        )?
+        .long_flag(
+            "fail-on-stale-ignores",
+            "exit with failure when ignore entries don't match any duplicate",
+            &mut opts.fail_on_stale_ignores,
+            false,
+        )?
+        .long_flag(
+            "no-fail-on-stale-ignores",
+            "don't fail on stale ignore entries, overrides the configuration file",
+            &mut opts.no_fail_on_stale_ignores,
+            false,
+        )?
+        .long_arg(
+            "diff",
+            "only report duplicates which touch lines added or changed by this unified diff, \
+            - for stdin",
+            &mut opts.diff,
+            Some("<patch|->"),
+            false,
+        )?
+        .long_arg(
+            "git-diff",
+            "only report duplicates which touch lines changed since this git commit, or \
+            untracked files",
+            &mut opts.git_diff,
+            Some("<base>"),
+            false,
+        )?
+        .long_arg(
+            "cache",
+            "file to keep line signatures in between runs, so only changed files are read",
+            &mut opts.cache,
+            Some("<file>"),
+            false,
+        )?
+        .long_arg(
+            "root",
+            "show file names relative to this directory, defaults to the git top level \
+            directory or the current working directory",
+            &mut opts.root,
+            Some("<dir>"),
+            false,
+        )?
         .arg(
             't',
             "threads",
@@ -698,13 +2298,296 @@ fn main() -> Result<(), rags::Error> {
             Some("<thread number>"),
             false,
         )?
+        .done()?
+        .subcommand(
+            "show",
+            "show the instances of duplicates side by side against the first one, \
+            highlighting what differs",
+            &mut opts.subcmds,
+            None,
+        )?
+        .long_arg(
+            "width",
+            "total width of the side by side output",
+            &mut opts.width,
+            Some("<columns>"),
+            false,
+        )?
+        .positional_list(
+            "hash",
+            "hash signature(s) of the duplicates to show, defaults to all",
+            &mut opts.show_keys,
+            false,
+        )?
+        .done()?
+        .subcommand(
+            "search",
+            "look for existing copies of a snippet of text, longest matches first",
+            &mut opts.subcmds,
+            None,
+        )?
+        .positional(
+            "snippet",
+            "file with the text to look for, defaults to stdin",
+            &mut opts.snippet,
+            false,
+        )?
+        .done()?
+        .subcommand(
+            "scan",
+            "scan the files, the default, or with --against only look for copies of an index",
+            &mut opts.subcmds,
+            None,
+        )?
+        .long_arg(
+            "against",
+            "index of a corpus to look for copies of, built with index build",
+            &mut opts.against,
+            Some("<index>"),
+            false,
+        )?
+        .positional_list(
+            "pattern",
+            "pattern or file to scan, the same as -f",
+            &mut opts.file_globs,
+            false,
+        )?
+        .done()?
+        .subcommand(
+            "index",
+            "build an index of a corpus to check new code against with scan --against",
+            &mut opts.subcmds,
+            None,
+        )?
+        .subcommand(
+            "build",
+            "hash the files and write the index",
+            &mut opts.subcmds,
+            None,
+        )?
+        .positional("index", "index file to write", &mut opts.index_file, true)?
+        .positional_list(
+            "pattern",
+            "pattern or file to index, the same as -f",
+            &mut opts.file_globs,
+            false,
+        )?
+        .done()?
+        .done()?
+        .subcommand(
+            "report",
+            "write the reports for a saved JSON report again, without scanning",
+            &mut opts.subcmds,
+            None,
+        )?
+        .positional("report", "JSON report to read", &mut opts.report_file, true)?
+        .done()?
+        .subcommand(
+            "compare",
+            "list the duplicates which are new, removed or changed between two saved JSON \
+            reports, --format is one of text, json, markdown",
+            &mut opts.subcmds,
+            None,
+        )?
+        .positional("old", "earlier JSON report", &mut opts.old_report, true)?
+        .positional("new", "later JSON report", &mut opts.new_report, true)?
+        .done()?
+        .subcommand(
+            "baseline",
+            "create or update a baseline file of duplicates to ignore",
+            &mut opts.subcmds,
+            None,
+        )?
+        .long_arg(
+            "reason",
+            "reason recorded for the added entries",
+            &mut opts.reason,
+            Some("<text>"),
+            false,
+        )?
+        .long_arg(
+            "author",
+            "author recorded for the added entries",
+            &mut opts.author,
+            Some("<name>"),
+            false,
+        )?
+        .subcommand(
+            "create",
+            "write every current duplicate to a new baseline file",
+            &mut opts.subcmds,
+            None,
+        )?
+        .positional("file", "baseline file", &mut opts.baseline_file, true)?
+        .done()?
+        .subcommand(
+            "update",
+            "add new duplicates to a baseline file and remove entries which no longer match",
+            &mut opts.subcmds,
+            None,
+        )?
+        .positional("file", "baseline file", &mut opts.baseline_file, true)?
+        .done()?
         .done()?;
 
+    Ok(())
+}
+
+fn main() {
+    let mut opts = Options::default();
+
+    // The configuration file supplies the defaults for the command line.
+    let args: Vec<String> = std::env::args().collect();
+    let config_lists = match config::requested(&args) {
+        Some(f) => match config::apply(&f, &mut opts) {
+            Ok(lists) => lists,
+            Err(e) => {
+                eprintln!("ERROR: {}", e);
+                process::exit(EXIT_ERROR);
+            }
+        },
+        None => config::Lists::default(),
+    };
+
+    let mut parser = argparse!();
+    if let Err(e) = parse_args(&mut parser, &mut opts) {
+        eprintln!("ERROR: {}", e);
+        process::exit(EXIT_ERROR);
+    }
+
+    opts.lines_given = on_command_line(&args, &["-l", "--lines"]);
+    opts.normalize_given = on_command_line(&args, &["--normalize"]);
+    if !opts.against.is_empty() {
+        for (used, name) in [
+            (!opts.left.is_empty(), "--left and --right"),
+            (!opts.base.is_empty(), "--base"),
+            (!opts.submissions.is_empty(), "--submissions"),
+            (opts.depth.0.is_some(), "--depth"),
+        ] {
+            if used {
+                eprintln!("ERROR: {} can't be used with scan --against", name);
+                process::exit(EXIT_ERROR);
+            }
+        }
+    }
+
+    // The configuration file can turn these on, the command line has the last word.
+    if opts.no_print {
+        opts.print = false;
+    }
+    if opts.no_fail_on_new {
+        opts.fail_on_new = false;
+    }
+    if opts.no_fail_on_stale_ignores {
+        opts.fail_on_stale_ignores = false;
+    }
+
+    if opts.left.is_empty() != opts.right.is_empty() {
+        eprintln!("ERROR: --left and --right have to be used together");
+        process::exit(EXIT_ERROR);
+    }
+    if !opts.left.is_empty() {
+        if !opts.file_globs.is_empty() {
+            eprintln!("ERROR: -f, --file can't be used with --left and --right");
+            process::exit(EXIT_ERROR);
+        }
+        opts.file_globs = opts.left.iter().chain(&opts.right).cloned().collect();
+    }
+    if !opts.submissions.is_empty() {
+        if !opts.left.is_empty() {
+            eprintln!("ERROR: --submissions can't be used with --left and --right");
+            process::exit(EXIT_ERROR);
+        }
+        let dir = match canonicalize(&opts.submissions) {
+            Ok(d) if d.is_dir() => d,
+            _ => {
+                eprintln!(
+                    "ERROR: --submissions {} is not a directory",
+                    opts.submissions
+                );
+                process::exit(EXIT_ERROR);
+            }
+        };
+        if opts.file_globs.is_empty() {
+            opts.file_globs = vec![format!(
+                "{}/**/*",
+                glob::Pattern::escape(&dir.to_string_lossy())
+            )];
+        }
+        opts.submissions_dir = Some(dir);
+    }
+    if opts.file_globs.is_empty() {
+        opts.file_globs = config_lists.files;
+    }
+    if opts.outputs.is_empty() && opts.format.is_empty() && !opts.json {
+        for o in &config_lists.outputs {
+            match o.parse() {
+                Ok(o) => opts.outputs.push(o),
+                Err(e) => {
+                    eprintln!("ERROR: configuration file output {}", e);
+                    process::exit(EXIT_ERROR);
+                }
+            }
+        }
+    }
+    let cwd = std::env::current_dir().unwrap_or_else(|_| PathBuf::from("."));
+    let cwd = canonicalize(&cwd).unwrap_or(cwd);
+    for e in &opts.excludes {
+        match config::absolute_pattern(&cwd, e) {
+            Ok(p) => opts.exclude_patterns.push(p),
+            Err(e) => {
+                eprintln!("ERROR: {}", e);
+                process::exit(EXIT_ERROR);
+            }
+        }
+    }
+    COLLAPSE_WHITESPACE.store(opts.normalize == Normalize::Whitespace, Ordering::Relaxed);
+
+    let subcmd = opts.subcmds.first().cloned().unwrap_or_default();
     if parser.wants_help() {
         parser.print_help();
+    } else if opts.json_schema {
+        print!("{}", report::JSON_SCHEMA);
+    } else if opts.file_globs.is_empty() && subcmd != "report" && subcmd != "compare" {
+        eprintln!("ERROR: at least one -f, --file <pattern or specific file> is required");
+        process::exit(EXIT_ERROR);
+    } else if subcmd == "compare" {
+        compare_reports(&opts);
     } else {
-        let results_hash: DashMap<u64, Collision>;
-        let mut ignore_hash: HashMap<u64, bool> = HashMap::new();
+        if !opts.format.is_empty() {
+            match opts.format.parse::<output::Format>() {
+                Ok(f) => opts.outputs.push(output::OutputSpec::stdout(f)),
+                Err(e) => {
+                    eprintln!("ERROR: --format {}", e);
+                    process::exit(EXIT_ERROR);
+                }
+            }
+        }
+        if opts.json {
+            opts.outputs
+                .push(output::OutputSpec::stdout(output::Format::Json));
+        }
+        if opts.outputs.is_empty() {
+            opts.outputs
+                .push(output::OutputSpec::stdout(output::Format::Text));
+        }
+
+        let filters = match filters(&opts) {
+            Ok(f) => f,
+            Err(e) => {
+                eprintln!("ERROR: {}", e);
+                process::exit(EXIT_ERROR);
+            }
+        };
+
+        if subcmd == "report" {
+            rewrite_report(&opts, &filters);
+            return;
+        }
+
+        let root = resolve_root(&opts.root, config_lists.dir.as_deref());
+        let changes = changed_lines(&opts, &root);
+        FILE_LOOKUP.lock().unwrap().set_root(root);
 
         // Dashmap scales well through ~3-4 threads, then stalls for our use case.
         if opts.threads != 0 {
@@ -714,33 +2597,243 @@ fn main() -> Result<(), rags::Error> {
                 .unwrap();
         }
 
-        {
-            if !opts.ignore.is_empty() {
-                ignore_hash = get_ignore_hashes(&opts.ignore);
+        if subcmd == "baseline" {
+            if changes.is_some() {
+                eprintln!("ERROR: --diff and --git-diff can't be used with baseline");
+                process::exit(EXIT_ERROR);
             }
+            // A baseline needs to see everything, so nothing is ignored.
+            let r = scan(&opts, &baseline::Matcher::empty(), changes.as_ref());
+            baseline_command(&r, &opts);
+        } else if subcmd == "index" {
+            index_build(&opts);
+        } else {
+            let mut r = if subcmd == "search" {
+                search(&opts)
+            } else {
+                let ignores = if opts.ignore.is_empty() {
+                    baseline::Matcher::empty()
+                } else {
+                    get_ignores(&opts.ignore, &opts)
+                };
+                if opts.against.is_empty() {
+                    scan(&opts, &ignores, changes.as_ref())
+                } else {
+                    scan_against(&mut opts, &ignores, changes.as_ref())
+                }
+            };
+            r.apply_filters(&filters);
+            write_report(&mut r, &opts);
+            check_gates(&r, &opts);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn markers() {
+        assert_eq!(parse_marker("int a;"), Ok(None));
+        assert_eq!(
+            parse_marker("// duplihere:ignore-next 12"),
+            Ok(Some(Marker::IgnoreNext(12)))
+        );
+        assert_eq!(
+            parse_marker("/* duplihere:ignore-next 3 */"),
+            Ok(Some(Marker::IgnoreNext(3)))
+        );
+        assert_eq!(parse_marker("# duplihere:off"), Ok(Some(Marker::Off)));
+        assert_eq!(parse_marker("<!-- duplihere:on -->"), Ok(Some(Marker::On)));
+        assert_eq!(parse_marker("  -- duplihere:off"), Ok(Some(Marker::Off)));
+        assert_eq!(parse_marker("; duplihere:on"), Ok(Some(Marker::On)));
+        assert_eq!(parse_marker("duplihere:online"), Ok(None));
+        assert_eq!(parse_marker("duplihere:ignore-nextline 2"), Ok(None));
+        assert!(parse_marker("// duplihere:ignore-next").is_err());
+        assert!(parse_marker("// duplihere:ignore-next lots").is_err());
+        assert!(parse_marker("// duplihere:ignore-next 99999999999").is_err());
+    }
+
+    #[test]
+    fn markers_outside_comments() {
+        assert_eq!(parse_marker("duplihere:off"), Ok(None));
+        assert_eq!(parse_marker("let s = \"<!-- duplihere:on -->\";"), Ok(None));
+        assert_eq!(parse_marker("x = \"# duplihere:off\""), Ok(None));
+        assert_eq!(parse_marker("    \"<!-- duplihere:on -->\","), Ok(None));
+        assert_eq!(
+            parse_marker("/// `duplihere:off` covers everything"),
+            Ok(None)
+        );
+        assert_eq!(parse_marker("// use `duplihere:ignore-next <n>`"), Ok(None));
+        assert_eq!(parse_marker("// see duplihere:ignore-next"), Ok(None));
+        assert_eq!(parse_marker("int a; // duplihere:off"), Ok(None));
+    }
 
-            let files_to_process: Vec<(u32, Arc<String>)> = files_to_process(&opts.file_globs);
+    #[test]
+    fn suppressed_ranges() {
+        let text = "a\n// duplihere:ignore-next 2\nb\nc\nd\n// duplihere:off\ne\n\
+                    // duplihere:on\nf\n// duplihere:off\ng\n";
+        let (sigs, suppressed) = signatures(text.as_bytes(), "test");
+        assert_eq!(sigs.len(), 11);
+        assert_eq!(suppressed, vec![(2, 4), (5, 8), (9, u32::MAX)]);
+    }
 
-            let collision_hashes: DashMap<u64, Vec<LineId>> = DashMap::new();
-            let file_hashes: Mutex<Vec<Vec<u64>>> =
-                Mutex::new(vec![vec![0; 0]; files_to_process.len()]);
+    #[test]
+    fn ignore_next_does_not_overflow() {
+        let text = "a\n// duplihere:ignore-next 4294967295\nb\n";
+        let (_, suppressed) = signatures(text.as_bytes(), "test");
+        assert_eq!(suppressed, vec![(2, u32::MAX)]);
+    }
+
+    #[test]
+    fn merged_ranges() {
+        assert_eq!(
+            merge_ranges(vec![(10, 12), (1, 3), (3, 5), (11, 20), (30, 31)]),
+            vec![(1, 5), (10, 20), (30, 31)]
+        );
+        assert_eq!(merge_ranges(vec![]), vec![]);
+    }
 
-            files_to_process.par_iter().for_each(|e| {
-                process_file(
-                    e.0,
-                    &e.1,
-                    opts.lines as usize,
-                    &file_hashes,
-                    &collision_hashes,
-                )
-            });
+    #[test]
+    fn instance_in_ranges() {
+        let ranges = [(10, 20)];
+        let at = |line_number| LineId {
+            file_id: 0,
+            line_number,
+        };
+        assert!(within(&ranges, &at(10), 10));
+        assert!(!within(&ranges, &at(8), 6));
+        assert!(touches(&ranges, &at(8), 6));
+        assert!(!touches(&ranges, &at(4), 6));
+        assert!(!within(&ranges, &at(15), 6));
+        assert!(touches(&ranges, &at(15), 6));
+    }
 
-            results_hash =
-                find_collisions(collision_hashes, &mut file_hashes.lock().unwrap(), &opts);
+    #[test]
+    fn base_is_subtracted() {
+        // Two files of 8 starter lines followed by the same 7 lines.
+        let starter: Vec<u64> = (1..=8).collect();
+        let file: Vec<u64> = starter.iter().copied().chain(101..=107).collect();
+        let mut file_hashes = vec![file.clone(), file];
+        let collision_hashes: DashMap<u64, Vec<LineId>> = DashMap::new();
+        for (file_id, f) in file_hashes.iter().enumerate() {
+            for (h, line_number) in rolling_hashes(f, 4) {
+                collision_hashes.entry(h).or_default().push(LineId {
+                    file_id: file_id as u32,
+                    line_number,
+                });
+            }
         }
+        let base: HashSet<u64> = rolling_hashes(&starter, 4)
+            .into_iter()
+            .map(|(h, _)| h)
+            .collect();
 
-        process_report(results_hash, &opts, &ignore_hash);
+        let covered = subtract_base(&collision_hashes, &mut file_hashes, &base, 4);
+        let expected: Vec<bool> = (0..15).map(|n| n < 8).collect();
+        assert_eq!(covered, vec![expected.clone(), expected]);
+        assert!(!collision_hashes.contains_key(&rolling_hashes(&starter, 4)[0].0));
+
+        let at = |file_id, line_number| LineId {
+            file_id,
+            line_number,
+        };
+        // A window straddling the starter lines doesn't match any more ...
+        assert!(maximize_collision(&file_hashes, &at(0, 6), &at(1, 6), 4).is_none());
+        // ... and a duplicate after them isn't extended back over them.
+        let c = maximize_collision(&file_hashes, &at(0, 8), &at(1, 8), 4).unwrap();
+        assert_eq!(c.num_lines, 7);
     }
 
-    Ok(())
+    #[test]
+    fn corpus_files_are_not_counted() {
+        let mut files = FileId::new();
+        assert_eq!(files.register_corpus_file(Arc::new("a.c".to_string())), 0);
+        assert_eq!(files.register_file(Arc::new("a.c".to_string())), Some(1));
+        assert_eq!(files.register_file(Arc::new("a.c".to_string())), None);
+        assert_eq!(files.register_file(Arc::new("b.c".to_string())), Some(2));
+        assert_eq!(files.number_files(), 2);
+    }
+
+    #[test]
+    fn gates() {
+        use report::{test_duplicate, test_report};
+
+        let mut r = test_report(vec![
+            test_duplicate(1, 10, &[("a.c", 1), ("b.c", 1)]),
+            test_duplicate(2, 5, &[("a.c", 20), ("b.c", 20), ("c.c", 1)]),
+        ]);
+        r.summary.scanned_lines = 100;
+        let mut opts = Options::default();
+        assert!(gate_failures(&r, &opts).is_empty());
+
+        opts.max_duplicate_lines = Limit(Some(20));
+        opts.max_duplication_percent = Limit(Some(20.0));
+        opts.max_clones = Limit(Some(2));
+        assert!(gate_failures(&r, &opts).is_empty());
+
+        opts.max_duplicate_lines = Limit(Some(19));
+        opts.max_duplication_percent = Limit(Some(19.5));
+        opts.max_clones = Limit(Some(1));
+        let failures = gate_failures(&r, &opts);
+        assert_eq!(failures.len(), 3);
+        assert!(failures[0].contains("--max-duplicate-lines"));
+        assert!(failures[1].contains("--max-duplication-percent"));
+        assert!(failures[2].contains("--max-clones"));
+    }
+
+    #[test]
+    fn fail_on_new_gate() {
+        use baseline::BaselineStatus;
+        use report::{test_duplicate, test_report};
+
+        let opts = Options {
+            fail_on_new: true,
+            ..Default::default()
+        };
+        let with = |status: Option<BaselineStatus>| {
+            let mut d = test_duplicate(1, 10, &[("a.c", 1), ("b.c", 1)]);
+            d.baseline_status = status;
+            gate_failures(&test_report(vec![d]), &opts)
+        };
+        // Without a baseline everything is new.
+        assert_eq!(with(None).len(), 1);
+        assert_eq!(with(Some(BaselineStatus::New)).len(), 1);
+        assert!(with(Some(BaselineStatus::Grown)).is_empty());
+        assert!(with(Some(BaselineStatus::Unchanged)).is_empty());
+        assert!(gate_failures(&test_report(vec![]), &opts).is_empty());
+    }
+
+    #[test]
+    fn stale_ignores_gate() {
+        let mut r = report::test_report(vec![]);
+        r.stale_ignores = vec![7];
+        let mut opts = Options::default();
+        assert!(gate_failures(&r, &opts).is_empty());
+        opts.fail_on_stale_ignores = true;
+        assert_eq!(gate_failures(&r, &opts).len(), 1);
+    }
+
+    #[test]
+    fn options_on_command_line() {
+        let args = |a: &[&str]| -> Vec<String> {
+            std::iter::once("duplihere")
+                .chain(a.iter().copied())
+                .map(String::from)
+                .collect()
+        };
+        let lines = ["-l", "--lines"];
+        assert!(on_command_line(&args(&["-f", "*.c", "-l", "6"]), &lines));
+        assert!(on_command_line(&args(&["-l8"]), &lines));
+        assert!(on_command_line(&args(&["--lines=8"]), &lines));
+        assert!(on_command_line(&args(&["--lines", "8"]), &lines));
+        assert!(!on_command_line(&args(&["--lines-x", "8"]), &lines));
+        assert!(!on_command_line(&args(&["-f", "*.c"]), &lines));
+        assert!(!on_command_line(&args(&["--", "-l", "6"]), &lines));
+        assert!(on_command_line(
+            &args(&["--normalize=whitespace"]),
+            &["--normalize"]
+        ));
+    }
 }
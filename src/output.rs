@@ -0,0 +1,996 @@
+// SPDX-License-Identifier: GPL-3.0-only
+//
+// Copyright (C) 2019-2023 Tony Asleson <tony.asleson@gmail.com>
+
+//! Report writers.  A single analysis produces a `Report` which can then be rendered by one or
+//! more reporters, each writing to its own destination.
+
+use serde_json::json;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Component, Path, PathBuf};
+use std::str::FromStr;
+
+use crate::baseline::BaselineStatus;
+use crate::file_directories;
+use crate::report::{Duplicate, Report};
+use crate::snippet::{self, ColorChoice, PrintInstances, PrintOptions};
+
+/// The supported output formats.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    Text,
+    Json,
+    Sarif,
+    Html,
+    Quickfix,
+    Lcov,
+    Dot,
+    Graphml,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(Format::Text),
+            "json" => Ok(Format::Json),
+            "sarif" => Ok(Format::Sarif),
+            "html" => Ok(Format::Html),
+            "quickfix" => Ok(Format::Quickfix),
+            "lcov" => Ok(Format::Lcov),
+            "dot" => Ok(Format::Dot),
+            "graphml" => Ok(Format::Graphml),
+            _ => Err(format!(
+                "unknown format '{}', expected one of text, json, sarif, html, quickfix, lcov, \
+                dot, graphml",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Format::Text => "text",
+            Format::Json => "json",
+            Format::Sarif => "sarif",
+            Format::Html => "html",
+            Format::Quickfix => "quickfix",
+            Format::Lcov => "lcov",
+            Format::Dot => "dot",
+            Format::Graphml => "graphml",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+impl Format {
+    /// True for the formats which include the text of duplicates, with `-p, --print`.
+    pub fn includes_text(self) -> bool {
+        matches!(self, Format::Json | Format::Html)
+    }
+}
+
+/// Where to write a report and in what format, parsed from `<format>[=<path>]`.  A missing path
+/// or a path of "-" is stdout.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct OutputSpec {
+    pub format: Format,
+    pub path: String,
+}
+
+impl OutputSpec {
+    pub fn stdout(format: Format) -> OutputSpec {
+        OutputSpec {
+            format,
+            path: "-".to_string(),
+        }
+    }
+
+    fn is_stdout(&self) -> bool {
+        self.path.is_empty() || self.path == "-"
+    }
+}
+
+impl FromStr for OutputSpec {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (format, path) = match s.split_once('=') {
+            Some((f, p)) => (f, p),
+            None => (s, "-"),
+        };
+        Ok(OutputSpec {
+            format: format.parse()?,
+            path: path.to_string(),
+        })
+    }
+}
+
+impl fmt::Display for OutputSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}={}", self.format, self.path)
+    }
+}
+
+/// Something which can render a report to a sink.
+pub trait Reporter {
+    fn write(&self, report: &Report, out: &mut dyn Write) -> io::Result<()>;
+}
+
+/// Options which affect how reports are rendered, rather than what is in them.
+#[derive(Clone, Copy, Debug)]
+pub struct RenderOptions {
+    pub print: bool,
+    pub print_instances: PrintInstances,
+    pub context: usize,
+    pub color: ColorChoice,
+    /// Collapse files to the directories this deep in graphs.
+    pub depth: Option<usize>,
+}
+
+/// Human readable output, what duplihere has always printed.  When `print` is set the duplicated
+/// text is read back from the files.
+pub struct TextReporter {
+    pub print: Option<PrintOptions>,
+}
+
+impl TextReporter {
+    fn write_text(&self, report: &Report, d: &Duplicate, out: &mut dyn Write) -> io::Result<()> {
+        let opts = match self.print {
+            Some(opts) => opts,
+            None => return Ok(()),
+        };
+        let wanted = match opts.instances {
+            PrintInstances::First => &d.instances[..1],
+            _ => &d.instances[..],
+        };
+
+        let mut first_body: Option<Vec<String>> = None;
+        for (n, i) in wanted.iter().enumerate() {
+            let excerpt = report.excerpt(i, d.num_lines, opts.context, Some(d.key));
+
+            let excerpt = match excerpt {
+                Ok(e) => e,
+                Err(e) => {
+                    writeln!(
+                        out,
+                        "{}",
+                        snippet::paint(
+                            opts.color,
+                            snippet::YELLOW,
+                            &format!("{}: {}, not showing text", i.path, e)
+                        )
+                    )?;
+                    continue;
+                }
+            };
+
+            if opts.instances == PrintInstances::Differing {
+                if n == 0 {
+                    first_body = Some(excerpt.body().to_vec());
+                } else if first_body.as_deref() == Some(excerpt.body()) {
+                    continue;
+                }
+            }
+
+            writeln!(
+                out,
+                "{}",
+                snippet::paint(
+                    opts.color,
+                    snippet::BOLD,
+                    &format!("--- {}:{}-{}", i.path, i.start_line, i.end_line)
+                )
+            )?;
+            snippet::write_excerpt(out, &excerpt, opts.color)?;
+        }
+        Ok(())
+    }
+}
+
+impl Reporter for TextReporter {
+    fn write(&self, report: &Report, out: &mut dyn Write) -> io::Result<()> {
+        for d in &report.duplicates {
+            writeln!(
+                out,
+                "{}\nHash signature = {}\nFound {} copy & pasted lines in the following files:",
+                "*".repeat(80),
+                d.key,
+                d.num_lines
+            )?;
+
+            match (d.baseline_status, d.baseline_hash) {
+                (Some(status), Some(hash)) => writeln!(
+                    out,
+                    "Baseline status = {}, matched baseline entry {}",
+                    status, hash
+                )?,
+                (Some(status), None) => writeln!(out, "Baseline status = {}", status)?,
+                _ => {}
+            }
+
+            for i in &d.instances {
+                writeln!(
+                    out,
+                    "Between lines {} and {} in {}",
+                    i.start_line, i.end_line, i.path
+                )?;
+            }
+
+            self.write_text(report, d, out)?;
+        }
+
+        if !report.stale_ignores.is_empty() {
+            writeln!(
+                out,
+                "{}\nThe following {} ignore entries did not match any duplicate:",
+                "*".repeat(80),
+                report.stale_ignores.len()
+            )?;
+            for k in &report.stale_ignores {
+                writeln!(out, "{}", k)?;
+            }
+        }
+
+        for g in &report.groups {
+            writeln!(
+                out,
+                "{}: {} of {} lines ({:.2}%) in {} of {} files are duplicated in the other set",
+                g.name,
+                g.duplicate_lines,
+                g.lines,
+                g.percent(),
+                g.files_duplicated,
+                g.files
+            )?;
+        }
+
+        if !report.submissions.is_empty() {
+            write_similarity(report, out)?;
+        }
+        if !report.directories.is_empty() {
+            write_directories(report, out)?;
+        }
+
+        let s = &report.summary;
+        let suppressed = if s.suppressed_instances > 0 {
+            format!(
+                ", {} instances suppressed inline ({} chunks entirely)",
+                s.suppressed_instances, s.suppressed_clones
+            )
+        } else {
+            "".to_string()
+        };
+        writeln!(
+            out,
+            "Found {} duplicate lines in {} chunks in {} files, {} chunks ignored{}.\n\
+            https://github.com/tasleson/duplihere",
+            s.duplicate_lines, s.clones, s.files, s.ignored, suppressed
+        )
+    }
+}
+
+/// Pairs of submissions ranked by how similar they are, then a matrix of the percentage of
+/// each submission's (row) lines which are in each of the others (column).
+fn write_similarity(report: &Report, out: &mut dyn Write) -> io::Result<()> {
+    writeln!(
+        out,
+        "{}\nSubmission similarity, most similar first:",
+        "*".repeat(80)
+    )?;
+    for p in &report.pairs {
+        writeln!(
+            out,
+            "{:7.2}% {} and {}, {} of {} lines ({:.2}%) and {} of {} lines ({:.2}%) shared",
+            p.similarity(),
+            p.left,
+            p.right,
+            p.left_shared,
+            p.left_lines,
+            p.left_percent(),
+            p.right_shared,
+            p.right_lines,
+            p.right_percent()
+        )?;
+    }
+
+    let names = &report.submissions;
+    let width = names.iter().map(|n| n.len()).max().unwrap_or(0);
+    let cell = |row: &str, col: &str| {
+        if row == col {
+            return "-".to_string();
+        }
+        let p = report.pairs.iter().find_map(|p| {
+            if p.left == row && p.right == col {
+                Some(p.left_percent())
+            } else if p.right == row && p.left == col {
+                Some(p.right_percent())
+            } else {
+                None
+            }
+        });
+        format!("{:.1}", p.unwrap_or(0.0))
+    };
+
+    writeln!(
+        out,
+        "{}\nPercentage of the row's lines found in the column:",
+        "*".repeat(80)
+    )?;
+    write!(out, "{:w$}", "", w = width + 6)?;
+    for i in 1..=names.len() {
+        write!(out, " {:>6}", format!("[{}]", i))?;
+    }
+    writeln!(out)?;
+    for (i, row) in names.iter().enumerate() {
+        write!(out, "{:>5} {:w$}", format!("[{}]", i + 1), row, w = width)?;
+        for col in names {
+            write!(out, " {:>6}", cell(row, col))?;
+        }
+        writeln!(out)?;
+    }
+    Ok(())
+}
+
+/// Duplication by directory, the most duplicated lines first.
+fn write_directories(report: &Report, out: &mut dyn Write) -> io::Result<()> {
+    let width = report
+        .directories
+        .iter()
+        .map(|d| d.path.len())
+        .chain(std::iter::once("Directory".len()))
+        .max()
+        .unwrap_or(0);
+    writeln!(
+        out,
+        "{}\n{:w$} {:>6} {:>10} {:>10} {:>7} {:>7} {:>7}",
+        "*".repeat(80),
+        "Directory",
+        "Files",
+        "Lines",
+        "Duplicate",
+        "%",
+        "Within",
+        "Shared",
+        w = width
+    )?;
+    for d in &report.directories {
+        writeln!(
+            out,
+            "{:w$} {:>6} {:>10} {:>10} {:>7.2} {:>7} {:>7}",
+            d.path,
+            d.files,
+            d.lines,
+            d.duplicate_lines,
+            d.percent(),
+            d.clones_within,
+            d.clones_shared,
+            w = width
+        )?;
+    }
+    Ok(())
+}
+
+/// The versioned JSON report, see `report::JSON_SCHEMA`.
+pub struct JsonReporter;
+
+impl Reporter for JsonReporter {
+    fn write(&self, report: &Report, out: &mut dyn Write) -> io::Result<()> {
+        serde_json::to_writer_pretty(&mut *out, report)?;
+        writeln!(out)
+    }
+}
+
+/// SARIF 2.1.0, understood by most code scanning front ends.  Each duplicate is a single result
+/// located at its first instance with the other instances as related locations.
+pub struct SarifReporter;
+
+/// SARIF wants URIs, relative paths are fine as is, absolute ones get a file scheme.
+fn sarif_uri(path: &str) -> String {
+    if path.starts_with('/') {
+        format!("file://{}", path)
+    } else {
+        path.replace('\\', "/")
+    }
+}
+
+impl Reporter for SarifReporter {
+    fn write(&self, report: &Report, out: &mut dyn Write) -> io::Result<()> {
+        let location = |path: &str, start: u32, end: u32| {
+            json!({
+                "physicalLocation": {
+                    "artifactLocation": { "uri": sarif_uri(path) },
+                    "region": { "startLine": start, "endLine": end }
+                }
+            })
+        };
+
+        let results: Vec<serde_json::Value> = report
+            .duplicates
+            .iter()
+            .map(|d| {
+                let first = &d.instances[0];
+                let related: Vec<serde_json::Value> = d.instances[1..]
+                    .iter()
+                    .enumerate()
+                    .map(|(n, i)| {
+                        let mut l = location(&i.path, i.start_line, i.end_line);
+                        l["id"] = json!(n + 1);
+                        l
+                    })
+                    .collect();
+                let mut r = json!({
+                    "ruleId": "duplicate-text",
+                    "level": "warning",
+                    "message": {
+                        "text": format!(
+                            "{} lines duplicated in {} places, hash signature {}",
+                            d.num_lines, d.instance_count, d.key
+                        )
+                    },
+                    "locations": [location(&first.path, first.start_line, first.end_line)],
+                    "relatedLocations": related,
+                    "partialFingerprints": { "duplihereKey/v1": d.key.to_string() }
+                });
+                if let Some(status) = d.baseline_status {
+                    r["baselineState"] = json!(match status {
+                        BaselineStatus::New => "new",
+                        BaselineStatus::Grown => "updated",
+                        BaselineStatus::Unchanged => "unchanged",
+                    });
+                }
+                r
+            })
+            .collect();
+
+        let sarif = json!({
+            "$schema": "https://json.schemastore.org/sarif-2.1.0.json",
+            "version": "2.1.0",
+            "runs": [{
+                "tool": {
+                    "driver": {
+                        "name": report.tool.name,
+                        "version": report.tool.version,
+                        "informationUri": "https://github.com/tasleson/duplihere",
+                        "rules": [{
+                            "id": "duplicate-text",
+                            "shortDescription": { "text": "Copy & pasted text" }
+                        }]
+                    }
+                },
+                "results": results
+            }]
+        });
+
+        serde_json::to_writer_pretty(&mut *out, &sarif)?;
+        writeln!(out)
+    }
+}
+
+/// Stand alone HTML page, one table per duplicate.
+pub struct HtmlReporter;
+
+fn html_escape(s: &str) -> String {
+    let mut rc = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => rc.push_str("&amp;"),
+            '<' => rc.push_str("&lt;"),
+            '>' => rc.push_str("&gt;"),
+            '"' => rc.push_str("&quot;"),
+            _ => rc.push(c),
+        }
+    }
+    rc
+}
+
+impl Reporter for HtmlReporter {
+    fn write(&self, report: &Report, out: &mut dyn Write) -> io::Result<()> {
+        let s = &report.summary;
+        writeln!(
+            out,
+            "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n\
+            <title>duplihere report</title>\n<style>\n\
+            body {{ font-family: sans-serif; }}\n\
+            table {{ border-collapse: collapse; margin-bottom: 1em; }}\n\
+            td, th {{ border: 1px solid #ccc; padding: 2px 8px; text-align: left; }}\n\
+            pre {{ background: #f4f4f4; padding: 4px; }}\n\
+            </style>\n</head>\n<body>\n<h1>duplihere report</h1>"
+        )?;
+        writeln!(
+            out,
+            "<p>Found {} duplicate lines in {} chunks in {} files, {} chunks ignored, \
+            {} instances suppressed inline. Scanned {} lines, generated {} by {} {}.</p>",
+            s.duplicate_lines,
+            s.clones,
+            s.files,
+            s.ignored,
+            s.suppressed_instances,
+            s.scanned_lines,
+            html_escape(&report.generated),
+            html_escape(&report.tool.name),
+            html_escape(&report.tool.version)
+        )?;
+
+        if !report.stale_ignores.is_empty() {
+            let stale: Vec<String> = report.stale_ignores.iter().map(|k| k.to_string()).collect();
+            writeln!(
+                out,
+                "<p>Ignore entries which did not match any duplicate: {}</p>",
+                stale.join(", ")
+            )?;
+        }
+
+        for d in &report.duplicates {
+            let status = match d.baseline_status {
+                Some(status) => format!(", {}", status),
+                None => "".to_string(),
+            };
+            writeln!(
+                out,
+                "<h2 id=\"{0}\">{1} lines, hash signature {0}{2}</h2>\n<table>\n\
+                <tr><th>File</th><th>Start</th><th>End</th></tr>",
+                d.key, d.num_lines, status
+            )?;
+            for i in &d.instances {
+                writeln!(
+                    out,
+                    "<tr><td>{}</td><td>{}</td><td>{}</td></tr>",
+                    html_escape(&i.path),
+                    i.start_line,
+                    i.end_line
+                )?;
+            }
+            writeln!(out, "</table>")?;
+            if let Some(snippet) = d.instances.first().and_then(|i| i.snippet.as_ref()) {
+                writeln!(out, "<pre>{}</pre>", html_escape(snippet))?;
+            }
+        }
+
+        writeln!(out, "</body>\n</html>")
+    }
+}
+
+/// Compiler style `path:line:col: message` lines, one per instance, for editor quickfix lists
+/// and problem matchers.  Paths are relative to `--root` when one was given, otherwise they are
+/// made relative to the current working directory when possible.
+pub struct QuickfixReporter;
+
+fn quickfix_path(report: &Report, path: &str) -> String {
+    if report.options.root.is_some() {
+        return path.to_string();
+    }
+    let full = Path::new(&report.root).join(path);
+    match std::env::current_dir() {
+        Ok(cwd) => relative_path(&cwd, &full).to_string_lossy().to_string(),
+        Err(_) => full.to_string_lossy().to_string(),
+    }
+}
+
+/// Express absolute path `to` relative to absolute directory `from`, using ".." as needed.
+fn relative_path(from: &Path, to: &Path) -> PathBuf {
+    let from: Vec<Component> = from.components().collect();
+    let to: Vec<Component> = to.components().collect();
+    let common = from.iter().zip(&to).take_while(|(a, b)| a == b).count();
+
+    let mut rc = PathBuf::new();
+    for _ in common..from.len() {
+        rc.push("..");
+    }
+    for c in &to[common..] {
+        rc.push(c);
+    }
+    rc
+}
+
+impl Reporter for QuickfixReporter {
+    fn write(&self, report: &Report, out: &mut dyn Write) -> io::Result<()> {
+        for d in &report.duplicates {
+            for (n, i) in d.instances.iter().enumerate() {
+                // Point each instance at the next one, wrapping around, so that every instance
+                // tells you where to look.
+                let other = &d.instances[(n + 1) % d.instances.len()];
+                let more = d.instances.len().saturating_sub(2);
+                write!(
+                    out,
+                    "{}:{}:1: duplicate of {} lines, also at {}:{}",
+                    quickfix_path(report, &i.path),
+                    i.start_line,
+                    d.num_lines,
+                    quickfix_path(report, &other.path),
+                    other.start_line
+                )?;
+                if more > 0 {
+                    write!(out, " (+{} more)", more)?;
+                }
+                writeln!(out)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// LCOV tracefile so coverage viewers can highlight duplicated lines.  Duplicated lines are
+/// reported as not covered and every other line as covered, so the coverage of a file is the
+/// percentage of it which isn't duplicated.  A report read back from JSON doesn't know the
+/// lengths of the files, so only the duplicated lines of the files with duplicates are in it.
+pub struct LcovReporter;
+
+impl Reporter for LcovReporter {
+    fn write(&self, report: &Report, out: &mut dyn Write) -> io::Result<()> {
+        let mut duplicated: BTreeMap<&str, BTreeSet<u32>> = BTreeMap::new();
+        for d in &report.duplicates {
+            for i in &d.instances {
+                duplicated
+                    .entry(&i.path)
+                    .or_default()
+                    .extend(i.start_line..=i.end_line);
+            }
+        }
+
+        // A report read back from JSON doesn't have the line counts of the files.
+        let files: Vec<(&str, Option<u64>)> = if report.file_lines.is_empty() {
+            duplicated.keys().map(|p| (*p, None)).collect()
+        } else {
+            report
+                .file_lines
+                .iter()
+                .map(|(p, n)| (p.as_str(), Some(*n)))
+                .collect()
+        };
+
+        writeln!(out, "TN:duplihere")?;
+        for (path, lines) in files {
+            writeln!(out, "SF:{}", path)?;
+            // Lines which are part of a duplicate aren't hit, every other line is.
+            let dups = duplicated.get(path);
+            let lines: Vec<(u32, bool)> = match (lines, dups) {
+                (Some(n), _) => (1..=n as u32)
+                    .map(|l| (l, !dups.is_some_and(|d| d.contains(&l))))
+                    .collect(),
+                (None, Some(dups)) => dups.iter().map(|&l| (l, false)).collect(),
+                (None, None) => vec![],
+            };
+            for (l, hit) in &lines {
+                writeln!(out, "DA:{},{}", l, *hit as u32)?;
+            }
+            writeln!(out, "LF:{}", lines.len())?;
+            writeln!(out, "LH:{}", lines.iter().filter(|(_, hit)| *hit).count())?;
+            writeln!(out, "end_of_record")?;
+        }
+        Ok(())
+    }
+}
+
+/// Graph of the files, or directories, coupled by duplication, as Graphviz DOT or GraphML.
+/// Edges are weighted by the number of duplicated lines the two ends share.
+pub struct GraphReporter {
+    pub graphml: bool,
+    pub depth: Option<usize>,
+}
+
+/// Nodes and the weighted edges between them, edges are (smaller, larger) node indexes.
+type Graph = (Vec<String>, BTreeMap<(usize, usize), u64>);
+
+impl GraphReporter {
+    fn node_name(&self, path: &str) -> String {
+        match self.depth {
+            Some(depth) => file_directories(path, depth)
+                .pop()
+                .unwrap_or_else(|| ".".to_string()),
+            None => path.to_string(),
+        }
+    }
+
+    fn graph(&self, report: &Report) -> Graph {
+        let mut nodes: Vec<String> = Vec::new();
+        let mut index: HashMap<String, usize> = HashMap::new();
+        let mut edges: BTreeMap<(usize, usize), u64> = BTreeMap::new();
+
+        for d in &report.duplicates {
+            let ids: Vec<usize> = d
+                .instances
+                .iter()
+                .map(|i| {
+                    let name = self.node_name(&i.path);
+                    *index.entry(name.clone()).or_insert_with(|| {
+                        nodes.push(name);
+                        nodes.len() - 1
+                    })
+                })
+                .collect();
+            for (n, a) in ids.iter().enumerate() {
+                for b in &ids[n + 1..] {
+                    if a != b {
+                        *edges.entry((*a.min(b), *a.max(b))).or_default() += d.num_lines as u64;
+                    }
+                }
+            }
+        }
+        (nodes, edges)
+    }
+}
+
+/// Quote a DOT identifier.
+fn dot_quote(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+impl Reporter for GraphReporter {
+    fn write(&self, report: &Report, out: &mut dyn Write) -> io::Result<()> {
+        let (nodes, edges) = self.graph(report);
+
+        if self.graphml {
+            writeln!(
+                out,
+                "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+                <graphml xmlns=\"http://graphml.graphdrawing.org/xmlns\">\n  \
+                <key id=\"path\" for=\"node\" attr.name=\"path\" attr.type=\"string\"/>\n  \
+                <key id=\"lines\" for=\"edge\" attr.name=\"lines\" attr.type=\"long\"/>\n  \
+                <graph id=\"duplihere\" edgedefault=\"undirected\">"
+            )?;
+            for (n, name) in nodes.iter().enumerate() {
+                writeln!(
+                    out,
+                    "    <node id=\"n{}\"><data key=\"path\">{}</data></node>",
+                    n,
+                    html_escape(name)
+                )?;
+            }
+            for ((a, b), lines) in &edges {
+                writeln!(
+                    out,
+                    "    <edge source=\"n{}\" target=\"n{}\"><data key=\"lines\">{}</data></edge>",
+                    a, b, lines
+                )?;
+            }
+            writeln!(out, "  </graph>\n</graphml>")
+        } else {
+            writeln!(out, "graph duplihere {{\n  node [shape=box];")?;
+            for name in &nodes {
+                writeln!(out, "  {};", dot_quote(name))?;
+            }
+            for ((a, b), lines) in &edges {
+                writeln!(
+                    out,
+                    "  {} -- {} [weight={}, label=\"{}\", penwidth={:.1}];",
+                    dot_quote(&nodes[*a]),
+                    dot_quote(&nodes[*b]),
+                    lines,
+                    lines,
+                    1.0 + (*lines as f64).log2()
+                )?;
+            }
+            writeln!(out, "}}")
+        }
+    }
+}
+
+/// Every instance of each duplicate side by side against the first instance, with the characters
+/// that differ highlighted.  This shows if a duplicate was copied verbatim or adapted.
+pub struct ShowReporter {
+    pub width: usize,
+    pub color: bool,
+}
+
+impl Reporter for ShowReporter {
+    fn write(&self, report: &Report, out: &mut dyn Write) -> io::Result<()> {
+        for d in &report.duplicates {
+            writeln!(
+                out,
+                "{}\nHash signature = {}, {} lines in {} places",
+                "*".repeat(80),
+                d.key,
+                d.num_lines,
+                d.instance_count
+            )?;
+
+            let mut excerpts = Vec::new();
+            for i in &d.instances {
+                let label = format!("{}:{}-{}", i.path, i.start_line, i.end_line);
+                match report.excerpt(i, d.num_lines, 0, Some(d.key)) {
+                    Ok(e) => excerpts.push((label, e)),
+                    Err(e) => writeln!(out, "{}: {}, not showing text", i.path, e)?,
+                }
+            }
+
+            if let Some(((first_label, first), rest)) = excerpts.split_first() {
+                for (label, other) in rest {
+                    if first.body() == other.body() {
+                        writeln!(out, "{} is identical to {}", label, first_label)?;
+                    } else {
+                        writeln!(out)?;
+                        snippet::write_side_by_side(
+                            out,
+                            (first_label, first),
+                            (label, other),
+                            self.width,
+                            self.color,
+                        )?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Reporter for the given format, `to_stdout` is used to decide on color.
+pub fn reporter(format: Format, render: &RenderOptions, to_stdout: bool) -> Box<dyn Reporter> {
+    match format {
+        Format::Text => Box::new(TextReporter {
+            print: if render.print {
+                Some(PrintOptions {
+                    instances: render.print_instances,
+                    context: render.context,
+                    color: render.color.enabled(to_stdout),
+                })
+            } else {
+                None
+            },
+        }),
+        Format::Json => Box::new(JsonReporter),
+        Format::Sarif => Box::new(SarifReporter),
+        Format::Html => Box::new(HtmlReporter),
+        Format::Quickfix => Box::new(QuickfixReporter),
+        Format::Lcov => Box::new(LcovReporter),
+        Format::Dot => Box::new(GraphReporter {
+            graphml: false,
+            depth: render.depth,
+        }),
+        Format::Graphml => Box::new(GraphReporter {
+            graphml: true,
+            depth: render.depth,
+        }),
+    }
+}
+
+/// Render the report to every requested output.
+pub fn write_reports(
+    report: &Report,
+    outputs: &[OutputSpec],
+    render: &RenderOptions,
+) -> io::Result<()> {
+    for o in outputs {
+        let r = reporter(o.format, render, o.is_stdout());
+        if o.is_stdout() {
+            let stdout = io::stdout();
+            let mut lock = stdout.lock();
+            r.write(report, &mut lock)?;
+            lock.flush()?;
+        } else {
+            let f = File::create(&o.path).map_err(|e| {
+                io::Error::new(e.kind(), format!("unable to create {}: {}", o.path, e))
+            })?;
+            let mut w = BufWriter::new(f);
+            r.write(report, &mut w)?;
+            w.flush()?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::report::{test_duplicate, test_report};
+
+    /// A report of one 3 line duplicate in a.c and b.c.
+    fn report() -> Report {
+        let mut r = test_report(vec![test_duplicate(42, 3, &[("a.c", 2), ("b.c", 1)])]);
+        r.file_lines = vec![
+            ("a.c".to_string(), 5),
+            ("b.c".to_string(), 3),
+            ("c.c".to_string(), 4),
+        ];
+        r
+    }
+
+    fn written(reporter: &dyn Reporter, r: &Report) -> String {
+        let mut out = Vec::new();
+        reporter.write(r, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn json_round_trips() {
+        let json = written(&JsonReporter, &report());
+        let v: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(v["schema_version"], 1);
+        assert_eq!(v["duplicates"][0]["instances"][1]["end_line"], 3);
+        assert!(v["duplicates"][0]["instances"][0].get("snippet").is_none());
+        assert!(v.get("file_lines").is_none());
+
+        let r: Report = serde_json::from_str(&json).unwrap();
+        assert_eq!(r.duplicates.len(), 1);
+        assert_eq!(r.duplicates[0].key, 42);
+        assert_eq!(r.summary.duplicate_lines, 3);
+    }
+
+    #[test]
+    fn sarif_results() {
+        let mut r = report();
+        r.duplicates[0].instances[1].path = "/src/b.c".to_string();
+        let v: serde_json::Value = serde_json::from_str(&written(&SarifReporter, &r)).unwrap();
+        assert_eq!(v["version"], "2.1.0");
+
+        let result = &v["runs"][0]["results"][0];
+        assert_eq!(result["ruleId"], "duplicate-text");
+        let region = &result["locations"][0]["physicalLocation"];
+        assert_eq!(region["artifactLocation"]["uri"], "a.c");
+        assert_eq!(region["region"]["startLine"], 2);
+        assert_eq!(region["region"]["endLine"], 4);
+        let related = &result["relatedLocations"][0];
+        assert_eq!(related["id"], 1);
+        assert_eq!(
+            related["physicalLocation"]["artifactLocation"]["uri"],
+            "file:///src/b.c"
+        );
+        assert_eq!(result["partialFingerprints"]["duplihereKey/v1"], "42");
+        assert!(result.get("baselineState").is_none());
+
+        r.duplicates[0].baseline_status = Some(BaselineStatus::Grown);
+        let v: serde_json::Value = serde_json::from_str(&written(&SarifReporter, &r)).unwrap();
+        assert_eq!(v["runs"][0]["results"][0]["baselineState"], "updated");
+    }
+
+    /// The DA, LF and LH records of each file in lcov output.
+    fn lcov_records(lcov: &str) -> Vec<(String, Vec<String>, usize, usize)> {
+        lcov.split("end_of_record\n")
+            .filter(|r| r.contains("SF:"))
+            .map(|r| {
+                let field = |name: &str| -> Vec<&str> {
+                    r.lines().filter_map(|l| l.strip_prefix(name)).collect()
+                };
+                (
+                    field("SF:")[0].to_string(),
+                    field("DA:").iter().map(|s| s.to_string()).collect(),
+                    field("LF:")[0].parse().unwrap(),
+                    field("LH:")[0].parse().unwrap(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn lcov_lines_found_match_records() {
+        let lcov = written(&LcovReporter, &report());
+        assert!(lcov.starts_with("TN:duplihere\n"));
+
+        let records = lcov_records(&lcov);
+        assert_eq!(records.len(), 3);
+        for (path, da, lf, lh) in &records {
+            assert_eq!(da.len(), *lf, "{}", path);
+            assert_eq!(
+                da.iter().filter(|d| d.ends_with(",1")).count(),
+                *lh,
+                "{}",
+                path
+            );
+        }
+        let a = &records[0];
+        assert_eq!(a.1, ["1,1", "2,0", "3,0", "4,0", "5,1"]);
+        // Every line of b.c is duplicated, none of c.c is.
+        assert_eq!((records[1].2, records[1].3), (3, 0));
+        assert_eq!(records[2].1, ["1,1", "2,1", "3,1", "4,1"]);
+        assert_eq!((records[2].2, records[2].3), (4, 4));
+    }
+
+    #[test]
+    fn lcov_without_line_counts() {
+        let mut r = report();
+        r.file_lines.clear();
+        let records = lcov_records(&written(&LcovReporter, &r));
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].0, "a.c");
+        assert_eq!(records[0].1, ["2,0", "3,0", "4,0"]);
+        assert_eq!((records[0].2, records[0].3), (3, 0));
+    }
+}
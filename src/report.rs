@@ -0,0 +1,884 @@
+// SPDX-License-Identifier: GPL-3.0-only
+//
+// Copyright (C) 2019-2023 Tony Asleson <tony.asleson@gmail.com>
+
+//! Versioned report model.  This is what gets written out as JSON and is the documented,
+//! stable interface for anything consuming duplihere results.  Line numbers are 1-based and
+//! inclusive, to match the text output.
+
+use glob::Pattern;
+use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+use std::collections::HashSet;
+use std::fmt;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::baseline::{BaselineStatus, Matcher};
+use crate::snippet::{self, Excerpt, ExcerptError};
+use crate::{Collision, Normalize, Options, FILE_LOOKUP};
+
+/// Bump this whenever a field is removed or changes meaning, adding optional fields is fine.
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// The name instances of text read from stdin have.
+pub const STDIN_NAME: &str = "<stdin>";
+
+/// JSON Schema document describing `Report`, printed with `--json-schema`.
+pub static JSON_SCHEMA: &str = include_str!("../schema/report-v1.schema.json");
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Tool {
+    pub name: String,
+    pub version: String,
+}
+
+/// The options which influence what ends up in the report.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ReportOptions {
+    pub lines: u32,
+    pub file_globs: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ignore: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub root: Option<String>,
+    #[serde(default = "default_normalize")]
+    pub normalize: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub exclude: Vec<String>,
+    /// The path sections of the configuration file.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub paths: Vec<PathOptions>,
+    /// How duplicates were matched to the `ignore` file.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub baseline_match: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub baseline_threshold: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub against: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub left: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub right: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub base: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub submissions: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub diff: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub git_diff: Option<String>,
+    /// The filters applied to the duplicates, in the order they were applied.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub filters: Vec<FilterOptions>,
+}
+
+fn default_normalize() -> String {
+    "trim".to_string()
+}
+
+/// An option which is only set when it isn't empty.
+fn given(s: &str) -> Option<String> {
+    if s.is_empty() {
+        None
+    } else {
+        Some(s.to_string())
+    }
+}
+
+impl ReportOptions {
+    fn new(opts: &Options) -> ReportOptions {
+        let ignore = given(&opts.ignore);
+        ReportOptions {
+            lines: opts.lines,
+            file_globs: opts.file_globs.clone(),
+            baseline_match: ignore.as_ref().map(|_| opts.baseline_match.to_string()),
+            baseline_threshold: ignore.as_ref().map(|_| opts.baseline_threshold),
+            ignore,
+            root: given(&opts.root),
+            normalize: opts.normalize.to_string(),
+            exclude: opts.excludes.clone(),
+            paths: opts
+                .path_rules
+                .iter()
+                .map(|r| PathOptions {
+                    pattern: r.pattern.as_str().to_string(),
+                    lines: r.settings.lines,
+                    exclude: r.settings.exclude,
+                })
+                .collect(),
+            against: given(&opts.against),
+            left: opts.left.clone(),
+            right: opts.right.clone(),
+            base: opts.base.clone(),
+            submissions: given(&opts.submissions),
+            diff: given(&opts.diff),
+            git_diff: given(&opts.git_diff),
+            filters: vec![],
+        }
+    }
+}
+
+/// The settings of a path section of the configuration file.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PathOptions {
+    pub pattern: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lines: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exclude: Option<bool>,
+}
+
+/// The filters applied to a report, as they were given on the command line.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct FilterOptions {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sort: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub top: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_instances: Option<usize>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub cross_file_only: bool,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub same_file_only: bool,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub path_filters: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Summary {
+    pub files: u32,
+    pub scanned_lines: u64,
+    pub duplicate_lines: u64,
+    pub clones: u64,
+    pub ignored: u64,
+    /// Duplicate instances dropped because of inline suppression markers.
+    #[serde(default)]
+    pub suppressed_instances: u64,
+    /// Duplicates dropped because fewer than two instances were left after suppression.
+    #[serde(default)]
+    pub suppressed_clones: u64,
+}
+
+/// Numbers gathered while scanning which end up in the summary.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ScanStats {
+    pub scanned_lines: u64,
+    pub suppressed_instances: u64,
+    pub suppressed_clones: u64,
+}
+
+/// One location of a duplicated chunk of text.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Instance {
+    pub path: String,
+    pub start_line: u32,
+    pub end_line: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub snippet: Option<String>,
+}
+
+/// A chunk of text which was found in two or more places.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Duplicate {
+    pub key: u64,
+    pub num_lines: u32,
+    pub instance_count: usize,
+    /// Lines times the extra instances, see `duplicate_lines`.
+    #[serde(default)]
+    pub score: u64,
+    pub instances: Vec<Instance>,
+    /// How this duplicate relates to the baseline, when one was supplied.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub baseline_status: Option<BaselineStatus>,
+    /// The hash of the baseline entry this duplicate was matched to.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub baseline_hash: Option<u64>,
+    /// Line signatures of the duplicated text, only available while scanning.
+    #[serde(skip)]
+    pub signatures: Vec<u64>,
+}
+
+impl Duplicate {
+    /// Number of lines which could be removed if this duplicate was consolidated.
+    pub fn duplicate_lines(&self) -> u64 {
+        self.num_lines as u64 * (self.instance_count as u64).saturating_sub(1)
+    }
+
+    /// True when the instances are in more than one file.
+    pub fn cross_file(&self) -> bool {
+        self.instances
+            .iter()
+            .any(|i| i.path != self.instances[0].path)
+    }
+}
+
+/// What duplicates are ordered by with `--sort`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SortKey {
+    /// Most lines first.
+    Lines,
+    /// Most instances first.
+    Instances,
+    /// Highest score first.
+    Score,
+    /// By the path and start line of the first instance.
+    Path,
+}
+
+impl FromStr for SortKey {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "lines" => Ok(SortKey::Lines),
+            "instances" => Ok(SortKey::Instances),
+            "score" => Ok(SortKey::Score),
+            "path" => Ok(SortKey::Path),
+            _ => Err(format!(
+                "unknown value '{}', expected one of lines, instances, score, path",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for SortKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            SortKey::Lines => "lines",
+            SortKey::Instances => "instances",
+            SortKey::Score => "score",
+            SortKey::Path => "path",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+/// Which duplicates to report and in what order.  These work on a finished report, so a saved
+/// one can be filtered the same way as a fresh scan.
+#[derive(Debug, Clone, Default)]
+pub struct Filters {
+    pub sort: Option<SortKey>,
+    pub top: Option<usize>,
+    pub min_instances: Option<usize>,
+    pub cross_file_only: bool,
+    pub same_file_only: bool,
+    /// Keep duplicates with an instance whose path matches one of these.
+    pub paths: Vec<Pattern>,
+}
+
+impl Filters {
+    /// The filters to record in the report, `None` when there aren't any.
+    fn options(&self) -> Option<FilterOptions> {
+        let rc = FilterOptions {
+            sort: self.sort.map(|s| s.to_string()),
+            top: self.top,
+            min_instances: self.min_instances,
+            cross_file_only: self.cross_file_only,
+            same_file_only: self.same_file_only,
+            path_filters: self.paths.iter().map(|p| p.as_str().to_string()).collect(),
+        };
+        if rc == FilterOptions::default() {
+            None
+        } else {
+            Some(rc)
+        }
+    }
+
+    fn keep(&self, d: &Duplicate) -> bool {
+        if matches!(self.min_instances, Some(m) if d.instance_count < m) {
+            return false;
+        }
+        if (self.cross_file_only || self.same_file_only) && d.cross_file() != self.cross_file_only {
+            return false;
+        }
+        self.paths.is_empty()
+            || d.instances
+                .iter()
+                .any(|i| self.paths.iter().any(|p| p.matches(&i.path)))
+    }
+}
+
+/// How much of a set of files, `--left` or `--right`, is duplicated in the other set.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GroupCoverage {
+    pub name: String,
+    pub files: u32,
+    pub lines: u64,
+    /// Files with at least one line duplicated in the other set.
+    pub files_duplicated: u32,
+    /// Lines duplicated in the other set, each line is only counted once.
+    pub duplicate_lines: u64,
+}
+
+impl GroupCoverage {
+    pub fn percent(&self) -> f64 {
+        percent(self.duplicate_lines, self.lines)
+    }
+}
+
+/// Lines two submissions share, with `--submissions`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SubmissionPair {
+    pub left: String,
+    pub right: String,
+    pub left_lines: u64,
+    pub right_lines: u64,
+    /// Lines of the left submission which are also in the right one.
+    pub left_shared: u64,
+    /// Lines of the right submission which are also in the left one.
+    pub right_shared: u64,
+}
+
+fn percent(part: u64, total: u64) -> f64 {
+    if total == 0 {
+        0.0
+    } else {
+        part as f64 * 100.0 / total as f64
+    }
+}
+
+impl SubmissionPair {
+    pub fn left_percent(&self) -> f64 {
+        percent(self.left_shared, self.left_lines)
+    }
+
+    pub fn right_percent(&self) -> f64 {
+        percent(self.right_shared, self.right_lines)
+    }
+
+    /// Percentage of the lines of both submissions which are shared.
+    pub fn similarity(&self) -> f64 {
+        percent(
+            self.left_shared + self.right_shared,
+            self.left_lines + self.right_lines,
+        )
+    }
+}
+
+/// Duplication within a directory, with `--depth`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DirectorySummary {
+    pub path: String,
+    pub files: u32,
+    pub lines: u64,
+    /// Lines in the directory which are part of a duplicate, each counted once.
+    pub duplicate_lines: u64,
+    /// Duplicates with all their instances in the directory.
+    pub clones_within: u64,
+    /// Duplicates with instances both in and outside the directory.
+    pub clones_shared: u64,
+}
+
+impl DirectorySummary {
+    pub fn new(path: String) -> DirectorySummary {
+        DirectorySummary {
+            path,
+            files: 0,
+            lines: 0,
+            duplicate_lines: 0,
+            clones_within: 0,
+            clones_shared: 0,
+        }
+    }
+
+    pub fn percent(&self) -> f64 {
+        percent(self.duplicate_lines, self.lines)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Report {
+    pub schema_version: u32,
+    pub tool: Tool,
+    pub generated: String,
+    /// Directory which relative instance paths are relative to.
+    #[serde(default)]
+    pub root: String,
+    pub options: ReportOptions,
+    pub summary: Summary,
+    pub duplicates: Vec<Duplicate>,
+    /// Ignore entries which didn't match any duplicate.
+    #[serde(default)]
+    pub stale_ignores: Vec<u64>,
+    /// Coverage of each set of files when only duplicates between two sets were looked for.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub groups: Vec<GroupCoverage>,
+    /// Submission names, with `--submissions`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub submissions: Vec<String>,
+    /// Pairs of submissions which share lines, most similar first.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub pairs: Vec<SubmissionPair>,
+    /// Duplication by directory, with `--depth`, the most duplicated lines first.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub directories: Vec<DirectorySummary>,
+    /// Number of lines in every scanned file, only available while scanning.
+    #[serde(skip)]
+    pub file_lines: Vec<(String, u64)>,
+    /// Text read from stdin by `search`, which instances at `STDIN_NAME` are in.
+    #[serde(skip)]
+    pub stdin: Option<Vec<u8>>,
+}
+
+impl Report {
+    /// Load a report written with the JSON format.
+    pub fn load(file_name: &str) -> Result<Report, String> {
+        let f = File::open(file_name)
+            .map_err(|e| format!("Unable to open report {}, reason {}", file_name, e))?;
+        let mut r: Report = serde_json::from_reader(BufReader::new(f))
+            .map_err(|e| format!("Invalid report {}, reason {}", file_name, e))?;
+        if r.schema_version > SCHEMA_VERSION {
+            return Err(format!(
+                "Report {} is schema version {}, we only understand up to {}",
+                file_name, r.schema_version, SCHEMA_VERSION
+            ));
+        }
+
+        if let Err(e) = r.options.normalize.parse::<Normalize>() {
+            return Err(format!("Invalid report {}, normalize {}", file_name, e));
+        }
+        for d in &r.duplicates {
+            if d.instances.len() < 2 {
+                return Err(format!(
+                    "Invalid report {}, duplicate {} has {} instance(s), it needs at least 2",
+                    file_name,
+                    d.key,
+                    d.instances.len()
+                ));
+            }
+            if let Some(i) = d
+                .instances
+                .iter()
+                .find(|i| i.start_line == 0 || i.end_line < i.start_line)
+            {
+                return Err(format!(
+                    "Invalid report {}, duplicate {} has lines {} to {} in {}",
+                    file_name, d.key, i.start_line, i.end_line, i.path
+                ));
+            }
+        }
+
+        // Reports from before the score was added don't have it.
+        for d in &mut r.duplicates {
+            d.score = d.duplicate_lines();
+        }
+        Ok(r)
+    }
+
+    /// Read the text of the instances which don't have it from the files, when they are still
+    /// there and haven't changed since the scan.
+    pub fn read_snippets(&mut self) {
+        let mut snippets = Vec::new();
+        for (n, d) in self.duplicates.iter().enumerate() {
+            for (m, i) in d.instances.iter().enumerate() {
+                if i.snippet.is_none() {
+                    if let Ok(e) = self.excerpt(i, d.num_lines, 0, Some(d.key)) {
+                        let text: String = e.body().iter().map(|l| format!("{}\n", l)).collect();
+                        snippets.push((n, m, text));
+                    }
+                }
+            }
+        }
+        for (n, m, text) in snippets {
+            self.duplicates[n].instances[m].snippet = Some(text);
+        }
+    }
+
+    /// Drop the duplicates the filters don't want and order what is left.  The summary is
+    /// updated to match the filters, but not `top`, so it still counts what was cut off.
+    pub fn apply_filters(&mut self, f: &Filters) {
+        self.options.filters.extend(f.options());
+        self.duplicates.retain(|d| f.keep(d));
+        self.summary.duplicate_lines = self.duplicates.iter().map(|d| d.duplicate_lines()).sum();
+        self.summary.clones = self.duplicates.len() as u64;
+
+        // Without a sort `top` takes the worst duplicates, those with the highest score.
+        let sort = f.sort.or_else(|| f.top.map(|_| SortKey::Score));
+        let first = |d: &Duplicate| {
+            d.instances
+                .first()
+                .map(|i| (i.path.clone(), i.start_line))
+                .unwrap_or_default()
+        };
+        match sort {
+            Some(SortKey::Lines) => self.duplicates.sort_by_key(|d| Reverse(d.num_lines)),
+            Some(SortKey::Instances) => self.duplicates.sort_by_key(|d| Reverse(d.instance_count)),
+            Some(SortKey::Score) => self.duplicates.sort_by_key(|d| Reverse(d.score)),
+            Some(SortKey::Path) => self.duplicates.sort_by_cached_key(first),
+            None => {}
+        }
+        if let Some(n) = f.top {
+            self.duplicates.truncate(n);
+        }
+    }
+
+    /// Percentage of the scanned lines which are duplicates.
+    pub fn duplication_percent(&self) -> f64 {
+        if self.summary.scanned_lines == 0 {
+            0.0
+        } else {
+            self.summary.duplicate_lines as f64 * 100.0 / self.summary.scanned_lines as f64
+        }
+    }
+
+    /// Build the report from the final, de-duplicated and sorted collisions.  Collisions which
+    /// the baseline `ignores` accepts are counted, but left out.  When `reported` is given only
+    /// the collisions it is true for are included, the rest are only matched to the baseline.
+    pub fn new(
+        results: &[Collision],
+        reported: Option<&[bool]>,
+        opts: &Options,
+        file_hashes: &[Vec<u64>],
+        ignores: &Matcher,
+        stats: ScanStats,
+    ) -> Report {
+        let file_lookup_locked = FILE_LOOKUP.lock().unwrap();
+        let mut duplicates = Vec::new();
+        let mut ignored: u64 = 0;
+        let mut used: HashSet<u64> = HashSet::new();
+
+        for (n, p) in results.iter().enumerate() {
+            let first = &p.start_lines[0];
+            let start = first.line_number as usize;
+            let mut d = Duplicate {
+                key: p.key,
+                num_lines: p.num_lines,
+                instance_count: p.start_lines.len(),
+                score: 0,
+                instances: p
+                    .start_lines
+                    .iter()
+                    .map(|l| Instance {
+                        path: file_lookup_locked.id_to_display_name(l.file_id),
+                        start_line: l.line_number + 1,
+                        end_line: l.line_number + p.num_lines,
+                        snippet: None,
+                    })
+                    .collect(),
+                baseline_status: None,
+                baseline_hash: None,
+                signatures: file_hashes[first.file_id as usize]
+                    [start..start + p.num_lines as usize]
+                    .to_vec(),
+            };
+
+            let classified = ignores.classify(&d);
+            if let Some((_, hash)) = classified {
+                used.insert(hash);
+            }
+            if matches!(reported, Some(r) if !r[n]) {
+                continue;
+            }
+
+            if let Some((status, hash)) = classified {
+                if status == BaselineStatus::Unchanged {
+                    ignored += 1;
+                    continue;
+                }
+                d.baseline_status = Some(status);
+                d.baseline_hash = Some(hash);
+            } else if !ignores.is_empty() {
+                d.baseline_status = Some(BaselineStatus::New);
+            }
+
+            d.score = d.duplicate_lines();
+            duplicates.push(d);
+        }
+
+        let mut stale_ignores: Vec<u64> = ignores.hashes().filter(|k| !used.contains(k)).collect();
+        stale_ignores.sort_unstable();
+
+        Report {
+            schema_version: SCHEMA_VERSION,
+            tool: Tool {
+                name: env!("CARGO_PKG_NAME").to_string(),
+                version: env!("CARGO_PKG_VERSION").to_string(),
+            },
+            generated: utc_timestamp(SystemTime::now()),
+            root: file_lookup_locked
+                .root
+                .as_ref()
+                .map(|r| r.to_string_lossy().to_string())
+                .unwrap_or_default(),
+            options: ReportOptions::new(opts),
+            summary: Summary {
+                files: file_lookup_locked.number_files(),
+                scanned_lines: stats.scanned_lines,
+                duplicate_lines: duplicates.iter().map(|d| d.duplicate_lines()).sum(),
+                clones: duplicates.len() as u64,
+                ignored,
+                suppressed_instances: stats.suppressed_instances,
+                suppressed_clones: stats.suppressed_clones,
+            },
+            duplicates,
+            stale_ignores,
+            groups: vec![],
+            submissions: vec![],
+            pairs: vec![],
+            directories: vec![],
+            file_lines: (0..file_hashes.len() as u32)
+                .map(|i| {
+                    (
+                        file_lookup_locked.id_to_display_name(i),
+                        file_hashes[i as usize].len() as u64,
+                    )
+                })
+                .collect(),
+            stdin: None,
+        }
+    }
+
+    /// The text of an instance with `context` lines either side, see `snippet::read_excerpt`.
+    pub fn excerpt(
+        &self,
+        i: &Instance,
+        num_lines: u32,
+        context: usize,
+        key: Option<u64>,
+    ) -> Result<Excerpt, ExcerptError> {
+        let start = (i.start_line - 1) as usize;
+        match &self.stdin {
+            Some(text) if i.path == STDIN_NAME => {
+                snippet::excerpt(&text[..], start, num_lines as usize, context, key)
+            }
+            _ => snippet::read_excerpt(
+                &Path::new(&self.root).join(&i.path),
+                start,
+                num_lines as usize,
+                context,
+                key,
+            ),
+        }
+    }
+}
+
+/// Format a point in time as a RFC 3339 UTC timestamp, eg. "2023-09-01T14:03:27Z".
+pub fn utc_timestamp(t: SystemTime) -> String {
+    let secs = t
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let (year, month, day) = civil_from_days((secs / 86400) as i64);
+    let rem = secs % 86400;
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        rem / 3600,
+        (rem % 3600) / 60,
+        rem % 60
+    )
+}
+
+/// Convert days since the unix epoch to a (year, month, day), see
+/// http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+/// A duplicate of `num_lines` lines with an instance at each of the (path, start line), for
+/// tests.
+#[cfg(test)]
+pub(crate) fn test_duplicate(key: u64, num_lines: u32, at: &[(&str, u32)]) -> Duplicate {
+    let mut d = Duplicate {
+        key,
+        num_lines,
+        instance_count: at.len(),
+        score: 0,
+        instances: at
+            .iter()
+            .map(|&(path, start_line)| Instance {
+                path: path.to_string(),
+                start_line,
+                end_line: start_line + num_lines - 1,
+                snippet: None,
+            })
+            .collect(),
+        baseline_status: None,
+        baseline_hash: None,
+        signatures: vec![],
+    };
+    d.score = d.duplicate_lines();
+    d
+}
+
+/// A report of `duplicates` with a summary to match, for tests.
+#[cfg(test)]
+pub(crate) fn test_report(duplicates: Vec<Duplicate>) -> Report {
+    let mut r: Report = serde_json::from_value(serde_json::json!({
+        "schema_version": SCHEMA_VERSION,
+        "tool": { "name": "duplihere", "version": "0.0.0" },
+        "generated": "2023-09-01T14:03:27Z",
+        "options": { "lines": 3, "file_globs": ["*.c"] },
+        "summary": {
+            "files": 0, "scanned_lines": 0, "duplicate_lines": 0, "clones": 0, "ignored": 0
+        },
+        "duplicates": []
+    }))
+    .unwrap();
+    r.summary.duplicate_lines = duplicates.iter().map(|d| d.duplicate_lines()).sum();
+    r.summary.clones = duplicates.len() as u64;
+    r.duplicates = duplicates;
+    r
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn load(name: &str, duplicates: Vec<Duplicate>) -> Result<Report, String> {
+        let file_name =
+            std::env::temp_dir().join(format!("duplihere-{}-{}", name, std::process::id()));
+        let text = serde_json::to_string(&test_report(duplicates)).unwrap();
+        fs::write(&file_name, text).unwrap();
+        let rc = Report::load(&file_name.to_string_lossy());
+        fs::remove_file(&file_name).unwrap();
+        rc
+    }
+
+    /// A duplicate in a.c with instances at each of the (start, end) lines.
+    fn duplicate(lines: &[(u32, u32)]) -> Vec<Duplicate> {
+        let at: Vec<(&str, u32)> = lines.iter().map(|&(start, _)| ("a.c", start)).collect();
+        let mut d = test_duplicate(7, 3, &at);
+        for (i, &(_, end)) in d.instances.iter_mut().zip(lines) {
+            i.end_line = end;
+        }
+        // Left for `load` to fill in.
+        d.score = 0;
+        vec![d]
+    }
+
+    #[test]
+    fn loaded_reports_are_checked() {
+        let r = load("report-ok", duplicate(&[(1, 3), (5, 7)])).unwrap();
+        assert_eq!(r.duplicates[0].score, 3);
+        assert_eq!(r.options.normalize, "trim");
+
+        assert!(load("report-empty", duplicate(&[])).is_err());
+        assert!(load("report-single", duplicate(&[(1, 3)])).is_err());
+        assert!(load("report-zero", duplicate(&[(0, 2), (5, 7)])).is_err());
+        assert!(load("report-reversed", duplicate(&[(3, 1), (5, 7)])).is_err());
+    }
+
+    /// Duplicates of different lengths and instance counts, in and across files.
+    fn filtered(f: &Filters) -> (Vec<u64>, Report) {
+        let mut r = test_report(vec![
+            test_duplicate(1, 10, &[("src/a.c", 1), ("src/a.c", 20)]),
+            test_duplicate(2, 4, &[("src/a.c", 40), ("lib/b.c", 1), ("lib/c.c", 1)]),
+            test_duplicate(3, 6, &[("lib/b.c", 10), ("lib/c.c", 10)]),
+            test_duplicate(
+                4,
+                3,
+                &[
+                    ("lib/b.c", 20),
+                    ("lib/b.c", 30),
+                    ("lib/c.c", 20),
+                    ("lib/c.c", 30),
+                ],
+            ),
+        ]);
+        r.apply_filters(f);
+        (r.duplicates.iter().map(|d| d.key).collect(), r)
+    }
+
+    #[test]
+    fn unfiltered() {
+        let (keys, r) = filtered(&Filters::default());
+        assert_eq!(keys, [1, 2, 3, 4]);
+        assert!(r.options.filters.is_empty());
+    }
+
+    #[test]
+    fn sorted() {
+        let sort = |s| Filters {
+            sort: Some(s),
+            ..Default::default()
+        };
+        assert_eq!(filtered(&sort(SortKey::Lines)).0, [1, 3, 2, 4]);
+        assert_eq!(filtered(&sort(SortKey::Instances)).0, [4, 2, 1, 3]);
+        assert_eq!(filtered(&sort(SortKey::Score)).0, [1, 4, 2, 3]);
+        assert_eq!(filtered(&sort(SortKey::Path)).0, [3, 4, 1, 2]);
+    }
+
+    #[test]
+    fn top_is_by_score_without_a_sort() {
+        let (keys, r) = filtered(&Filters {
+            top: Some(2),
+            ..Default::default()
+        });
+        assert_eq!(keys, [1, 4]);
+        // The summary still counts what `top` cut off.
+        assert_eq!(r.summary.clones, 4);
+        assert_eq!(r.summary.duplicate_lines, 10 + 8 + 6 + 9);
+        assert_eq!(
+            r.options.filters,
+            [FilterOptions {
+                top: Some(2),
+                ..Default::default()
+            }]
+        );
+
+        let (keys, _) = filtered(&Filters {
+            top: Some(2),
+            sort: Some(SortKey::Lines),
+            ..Default::default()
+        });
+        assert_eq!(keys, [1, 3]);
+    }
+
+    #[test]
+    fn kept() {
+        let (keys, r) = filtered(&Filters {
+            min_instances: Some(3),
+            ..Default::default()
+        });
+        assert_eq!(keys, [2, 4]);
+        assert_eq!((r.summary.clones, r.summary.duplicate_lines), (2, 17));
+
+        let (keys, _) = filtered(&Filters {
+            cross_file_only: true,
+            ..Default::default()
+        });
+        assert_eq!(keys, [2, 3, 4]);
+
+        let (keys, r) = filtered(&Filters {
+            same_file_only: true,
+            ..Default::default()
+        });
+        assert_eq!(keys, [1]);
+        assert_eq!((r.summary.clones, r.summary.duplicate_lines), (1, 10));
+
+        let (keys, _) = filtered(&Filters {
+            paths: vec![Pattern::new("src/*").unwrap()],
+            ..Default::default()
+        });
+        assert_eq!(keys, [1, 2]);
+
+        let (keys, _) = filtered(&Filters {
+            min_instances: Some(3),
+            paths: vec![
+                Pattern::new("*/a.c").unwrap(),
+                Pattern::new("nothing").unwrap(),
+            ],
+            ..Default::default()
+        });
+        assert_eq!(keys, [2]);
+    }
+}
@@ -0,0 +1,408 @@
+// SPDX-License-Identifier: GPL-3.0-only
+//
+// Copyright (C) 2019-2023 Tony Asleson <tony.asleson@gmail.com>
+
+//! Reading duplicated text back out of the files and printing it for `--print`.
+
+use std::fmt;
+use std::fs::File;
+use std::io::{self, prelude::*, BufReader, IsTerminal};
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::signatures_key;
+
+/// Which instances of a duplicate to print.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PrintInstances {
+    /// Only the first instance, the text is the same for all of them after all.
+    First,
+    All,
+    /// The first instance and any others whose text differs from it, eg. in indentation.
+    Differing,
+}
+
+impl FromStr for PrintInstances {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "first" => Ok(PrintInstances::First),
+            "all" => Ok(PrintInstances::All),
+            "differing" => Ok(PrintInstances::Differing),
+            _ => Err(format!(
+                "unknown value '{}', expected one of first, all, differing",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for PrintInstances {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            PrintInstances::First => "first",
+            PrintInstances::All => "all",
+            PrintInstances::Differing => "differing",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ColorChoice {
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorChoice {
+    /// Should we use color when writing to stdout (`to_stdout`) or somewhere else.
+    pub fn enabled(self, to_stdout: bool) -> bool {
+        match self {
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+            ColorChoice::Auto => {
+                to_stdout && std::env::var_os("NO_COLOR").is_none() && io::stdout().is_terminal()
+            }
+        }
+    }
+}
+
+impl FromStr for ColorChoice {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(ColorChoice::Auto),
+            "always" => Ok(ColorChoice::Always),
+            "never" => Ok(ColorChoice::Never),
+            _ => Err(format!(
+                "unknown value '{}', expected one of auto, always, never",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for ColorChoice {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            ColorChoice::Auto => "auto",
+            ColorChoice::Always => "always",
+            ColorChoice::Never => "never",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+/// How to print the duplicated text.
+#[derive(Clone, Copy, Debug)]
+pub struct PrintOptions {
+    pub instances: PrintInstances,
+    pub context: usize,
+    pub color: bool,
+}
+
+pub const BOLD: &str = "\x1b[1m";
+pub const DIM: &str = "\x1b[2m";
+pub const GREEN: &str = "\x1b[32m";
+pub const RED_BG: &str = "\x1b[41m";
+pub const GREEN_BG: &str = "\x1b[42m";
+pub const YELLOW: &str = "\x1b[33m";
+pub const RESET: &str = "\x1b[0m";
+
+/// Wrap `s` in the ANSI `code` when color is enabled.
+pub fn paint(color: bool, code: &str, s: &str) -> String {
+    if color {
+        format!("{}{}{}", code, s, RESET)
+    } else {
+        s.to_string()
+    }
+}
+
+/// The duplicated lines of one instance and the lines surrounding it.
+#[derive(Debug)]
+pub struct Excerpt {
+    /// 0-based line number of the first line in `lines`.
+    pub first_line: usize,
+    /// Number of leading context lines.
+    pub before: usize,
+    /// Number of duplicated lines, which follow the leading context lines.
+    pub count: usize,
+    pub lines: Vec<String>,
+}
+
+impl Excerpt {
+    /// Just the duplicated lines.
+    pub fn body(&self) -> &[String] {
+        &self.lines[self.before..self.before + self.count]
+    }
+}
+
+/// Why we can't show the text of an instance.
+#[derive(Debug)]
+pub enum ExcerptError {
+    Unreadable(io::Error),
+    Changed,
+}
+
+impl fmt::Display for ExcerptError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExcerptError::Unreadable(e) => write!(f, "unable to read file, reason {}", e),
+            ExcerptError::Changed => write!(f, "file has changed since it was scanned"),
+        }
+    }
+}
+
+/// Read the `count` lines starting at 0-based `start_line` plus `context` lines either side.
+/// When `key` is supplied the duplicated lines are verified against it so we don't show text
+/// that no longer matches what we found.
+pub fn read_excerpt(
+    filename: &Path,
+    start_line: usize,
+    count: usize,
+    context: usize,
+    key: Option<u64>,
+) -> Result<Excerpt, ExcerptError> {
+    let file = File::open(filename).map_err(ExcerptError::Unreadable)?;
+    excerpt(BufReader::new(file), start_line, count, context, key)
+}
+
+/// The same as `read_excerpt`, for text from any reader.
+pub fn excerpt<R: BufRead>(
+    mut reader: R,
+    start_line: usize,
+    count: usize,
+    context: usize,
+    key: Option<u64>,
+) -> Result<Excerpt, ExcerptError> {
+    let first_line = start_line.saturating_sub(context);
+    let end = start_line + count + context;
+    let mut lines = Vec::new();
+    let mut buf: Vec<u8> = vec![];
+    let mut line_number = 0;
+
+    while line_number < end {
+        buf.clear();
+        if reader
+            .read_until(b'\n', &mut buf)
+            .map_err(ExcerptError::Unreadable)?
+            == 0
+        {
+            break;
+        }
+        if line_number >= first_line {
+            let l = String::from_utf8_lossy(&buf);
+            lines.push(l.trim_end_matches(['\n', '\r']).to_string());
+        }
+        line_number += 1;
+    }
+
+    let excerpt = Excerpt {
+        first_line,
+        before: start_line - first_line,
+        count,
+        lines,
+    };
+
+    if excerpt.lines.len() < excerpt.before + count {
+        return Err(ExcerptError::Changed);
+    }
+    if let Some(k) = key {
+        let sigs: Vec<u64> = excerpt
+            .body()
+            .iter()
+            .map(|l| crate::line_signature(l))
+            .collect();
+        if signatures_key(&sigs) != k {
+            return Err(ExcerptError::Changed);
+        }
+    }
+
+    Ok(excerpt)
+}
+
+/// Print an excerpt with a line number gutter, grep style, ':' for duplicated lines and '-' for
+/// context lines.
+pub fn write_excerpt(out: &mut dyn Write, excerpt: &Excerpt, color: bool) -> io::Result<()> {
+    let last = excerpt.first_line + excerpt.lines.len();
+    let width = last.to_string().len();
+
+    for (n, l) in excerpt.lines.iter().enumerate() {
+        let number = format!("{:>width$}", excerpt.first_line + n + 1, width = width);
+        if n >= excerpt.before && n < excerpt.before + excerpt.count {
+            writeln!(out, "{}: {}", paint(color, GREEN, &number), l)?;
+        } else {
+            writeln!(
+                out,
+                "{}- {}",
+                paint(color, DIM, &number),
+                paint(color, DIM, l)
+            )?;
+        }
+    }
+    Ok(())
+}
+
+/// For two lines, mark which characters are not part of their longest common subsequence, ie.
+/// the characters which differ.  Really long lines are just marked as entirely different when
+/// they aren't identical to keep this cheap.
+pub fn diff_chars(left: &[char], right: &[char]) -> (Vec<bool>, Vec<bool>) {
+    let (n, m) = (left.len(), right.len());
+    if left == right {
+        return (vec![false; n], vec![false; m]);
+    }
+    if n * m > 1_000_000 {
+        return (vec![true; n], vec![true; m]);
+    }
+
+    // lcs[i][j] is the LCS length of left[i..] and right[j..]
+    let mut lcs = vec![vec![0u32; m + 1]; n + 1];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            lcs[i][j] = if left[i] == right[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let mut l_diff = vec![true; n];
+    let mut r_diff = vec![true; m];
+    let (mut i, mut j) = (0, 0);
+    while i < n && j < m {
+        if left[i] == right[j] {
+            l_diff[i] = false;
+            r_diff[j] = false;
+            i += 1;
+            j += 1;
+        } else if lcs[i + 1][j] >= lcs[i][j + 1] {
+            i += 1;
+        } else {
+            j += 1;
+        }
+    }
+    (l_diff, r_diff)
+}
+
+/// Render one side of a side by side line into exactly `width` columns.  Tabs are shown as an
+/// arrow so they can be told apart from spaces, characters which differ are highlighted with
+/// `highlight` when using color, else they are left as is and the separator flags the line.
+fn render_column(
+    chars: &[char],
+    diff: &[bool],
+    width: usize,
+    color: bool,
+    highlight: &str,
+) -> String {
+    let mut rc = String::new();
+    let mut used = 0;
+
+    for (c, d) in chars.iter().zip(diff) {
+        let shown: String = match c {
+            '\t' => "\u{2192}   ".to_string(),
+            _ => c.to_string(),
+        };
+        let len = shown.chars().count();
+        if used + len > width {
+            if used < width {
+                rc.push('>');
+                used += 1;
+            }
+            break;
+        }
+        if color && *d {
+            rc.push_str(&paint(true, highlight, &shown));
+        } else {
+            rc.push_str(&shown);
+        }
+        used += len;
+    }
+    rc.push_str(&" ".repeat(width - used));
+    rc
+}
+
+/// Print `other` side by side against `first`, both are the duplicated lines of an instance.
+/// The separator between the columns is '|' when the lines are identical and '*' when they
+/// differ.
+pub fn write_side_by_side(
+    out: &mut dyn Write,
+    first: (&str, &Excerpt),
+    other: (&str, &Excerpt),
+    width: usize,
+    color: bool,
+) -> io::Result<()> {
+    let last =
+        (first.1.first_line + first.1.lines.len()).max(other.1.first_line + other.1.lines.len());
+    let num_width = last.to_string().len();
+    // "<num> <text> | <num> <text>"
+    let column = (width.saturating_sub(2 * (num_width + 1) + 3) / 2).max(10);
+
+    writeln!(
+        out,
+        "{}",
+        paint(
+            color,
+            BOLD,
+            &format!("{:<w$}   {}", first.0, other.0, w = column + num_width + 1)
+        )
+    )?;
+
+    for (n, (l, r)) in first.1.body().iter().zip(other.1.body()).enumerate() {
+        let l_chars: Vec<char> = l.chars().collect();
+        let r_chars: Vec<char> = r.chars().collect();
+        let (l_diff, r_diff) = diff_chars(&l_chars, &r_chars);
+        let same = l == r;
+
+        writeln!(
+            out,
+            "{:>nw$} {} {} {:>nw$} {}",
+            first.1.first_line + first.1.before + n + 1,
+            render_column(&l_chars, &l_diff, column, color, RED_BG),
+            if same {
+                "|".to_string()
+            } else {
+                paint(color, YELLOW, "*")
+            },
+            other.1.first_line + other.1.before + n + 1,
+            render_column(&r_chars, &r_diff, column, color, GREEN_BG).trim_end(),
+            nw = num_width
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn excerpt_with_context() {
+        let text = "a\nb\nc\nd\ne\n";
+        let e = excerpt(text.as_bytes(), 2, 2, 1, None).unwrap();
+        assert_eq!((e.first_line, e.before, e.count), (1, 1, 2));
+        assert_eq!(e.lines, vec!["b", "c", "d", "e"]);
+        assert_eq!(e.body(), ["c", "d"]);
+
+        let e = excerpt(text.as_bytes(), 0, 1, 3, None).unwrap();
+        assert_eq!((e.first_line, e.before), (0, 0));
+        assert!(matches!(
+            excerpt(text.as_bytes(), 4, 2, 0, None),
+            Err(ExcerptError::Changed)
+        ));
+    }
+
+    #[test]
+    fn excerpt_is_verified() {
+        let text = "a\n  b\nc\n";
+        let key = signatures_key(&[crate::line_signature("b"), crate::line_signature("c")]);
+        assert!(excerpt(text.as_bytes(), 1, 2, 0, Some(key)).is_ok());
+        assert!(matches!(
+            excerpt(text.as_bytes(), 0, 2, 0, Some(key)),
+            Err(ExcerptError::Changed)
+        ));
+    }
+}